@@ -1,5 +1,9 @@
-pub use memory_box::{LruCache, ExpireCache};
-pub use cache_macro::{cachable, lru_cache, expire_cache};
+#![allow(non_snake_case)]
+
+pub use memory_box::{Cache, EvictionPolicy, Lru, Fifo, Mru, Random, Lfu, LruCache, LfuCache, ArcCache, BoundedCache, TinyLfuCache, CountMinSketch, Doorkeeper, ClockCache, S3FifoCache, Slru, SlruCache, TwoQueueCache, ShardedLruCache, ExpireCache, Deadline, PersistedEntry, Expiry, TtlLruCache, TtlError, Clock, SystemClock, MockClock, Weigher, UnitWeigher, RemovalCause, Lookup, ReaperHandle};
+#[cfg(feature = "async")]
+pub use memory_box::{AsyncLruCache, AsyncExpireCache, TokioClock};
+pub use cache_macro::{cachable, lru_cache, lfu_cache, expire_cache};
 
 
 pub fn add(left: u64, right: u64) -> u64 {
@@ -1,8 +1,8 @@
-use cacheForge::{cachable, lru_cache, expire_cache};
+use cacheForge::{cachable, lru_cache, lfu_cache, expire_cache};
 use std::time::{Duration as StdDuration};
 use std::thread::sleep;
 
-#[expire_cache(size = 2)]
+#[expire_cache(size = 16, ttl = 2)]
 fn expensive_computation1(x: i32, y: i32) -> i32 {
     println!("Computing {} + {}", x, y); // This will print only when the function is not cached
     x + y
@@ -14,7 +14,31 @@ fn expensive_computation(x: i32, y: i32) -> i32 {
     x + y
 }
 
-#[cachable]
+#[lru_cache(size = 64, shards = 4)]
+fn square(x: u64) -> u64 {
+    println!("Squaring {}", x); // This will print only when the function is not cached
+    x * x
+}
+
+#[lru_cache(size = 16, policy = "clock")]
+fn lookup_name(id: u32) -> String {
+    println!("Looking up {}", id); // This will print only when the function is not cached
+    format!("user-{}", id)
+}
+
+#[lru_cache(size = 8, policy = "2q")]
+fn double(x: u64) -> u64 {
+    println!("Doubling {}", x); // This will print only when the function is not cached
+    x * 2
+}
+
+#[lfu_cache(size = 2)]
+fn cube(x: u64) -> u64 {
+    println!("Cubing {}", x); // This will print only when the function is not cached
+    x * x * x
+}
+
+#[cachable(policy = "fifo")]
 fn concatenate_strings(a: &str, b: &str) -> String {
     println!("Concatenating {} and {}", a, b); // This will print only when the function is not cached
     format!("{}{}", a, b)
@@ -31,12 +55,49 @@ fn main() {
     println!("{}", expensive_computation(1, 2)); // not in cache will compute
     println!("{}", expensive_computation(3, 4)); // Uses cache
 
+    // Shrink the cache at runtime, keeping the most recently used entry
+    println!("evicted {:?}", resize_expensive_computation_cache(1));
+    println!("{}", expensive_computation(3, 4)); // Uses cache
+
     // Test string concatenation
     println!("{}", concatenate_strings("Hello, ", "World!")); // Computes and caches
     println!("{}", concatenate_strings("Hello, ", "World!")); // Uses cache
     println!("{}", concatenate_strings("Rust", "Lang")); // Computes and caches
     println!("{}", concatenate_strings("Rust", "Lang")); // Uses cache
 
+    // Test the clock cache: repeated lookups only take a shared lock
+    for _ in 0..2 {
+        for id in 1..=3 {
+            println!("{}", lookup_name(id)); // Computes once, then uses cache
+        }
+    }
+
+    // Test the LFU cache: the hot key 2 survives a run of one-off keys
+    println!("{}", cube(2)); // Computes and caches
+    println!("{}", cube(2)); // Uses cache
+    for x in 10..13 {
+        println!("{}", cube(x)); // Computes and caches
+    }
+    println!("{}", cube(2)); // Uses cache
+
+    // Test the 2Q cache: a key requested again soon after leaving A1in
+    // moves to Am, where a scan of one-off keys cannot reach it
+    for x in 1..=9 {
+        println!("{}", double(x)); // Computes and caches
+    }
+    println!("{}", double(1)); // Recomputes, now cached in Am
+    for x in 100..110 {
+        println!("{}", double(x)); // Computes and caches
+    }
+    println!("{}", double(1)); // Uses cache
+
+    // Test the sharded cache from several threads without a global lock
+    let handles: Vec<_> = (0..4)
+        .map(|_| std::thread::spawn(|| (1..=4).map(square).sum::<u64>()))
+        .collect();
+    for handle in handles {
+        println!("{}", handle.join().unwrap());
+    }
 
 
     println!("{}", expensive_computation1(3, 4)); // Uses cache
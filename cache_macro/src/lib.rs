@@ -1,140 +1,166 @@
 use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use syn::{parse_macro_input, Item, FnArg, PatType, ReturnType, Lit, Meta, Expr};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Item, FnArg, PatType, ReturnType, Lit, MetaNameValue, Expr, Token, Type, Ident};
 
-#[proc_macro_attribute]
-pub fn lru_cache(attr: TokenStream, item: TokenStream) -> TokenStream {
-    // Parse the attribute to extract the cache size
-    let cache_size = if !attr.is_empty() {
-        let parsed_attr = parse_macro_input!(attr as Meta);
-        match parsed_attr {
-            Meta::NameValue(name_value) => {
-                // Accessing the value as a reference to Expr
-                if let Expr::Lit(expr_lit) = name_value.value {
-                    if let Lit::Int(lit_int) = expr_lit.lit {
-                        lit_int.base10_parse::<usize>().unwrap_or(2) // Default to 2 if parsing fails
-                    } else {
-                        2
-                    }
-                } else {
-                    2 // Default to 2 if the value isn't a literal expression
-                }
-            }
-            _ => 2, // Default to 2 if no valid size is provided
-        }
-    } else {
-        2 // Default size if no attribute is provided
-    };
-
-    let input = parse_macro_input!(item as Item);
+/// Name/value pairs given to a cache attribute, e.g. `size = 10, shards = 4`.
+struct CacheArgs {
+    values: Vec<(String, Lit)>,
+}
 
-    if let Item::Fn(input_fn) = input {
-        let fn_name = &input_fn.sig.ident;
-        let fn_args = &input_fn.sig.inputs;
-        let fn_return_type = match &input_fn.sig.output {
-            ReturnType::Type(_, ty) => ty.clone(),
-            ReturnType::Default => {
-                return TokenStream::from(quote! {
-                    compile_error!("Functions with #[cachable] must have a return type.");
-                });
+impl Parse for CacheArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let pairs = Punctuated::<MetaNameValue, Token![,]>::parse_terminated(input)?;
+        let mut values = Vec::new();
+        for pair in pairs {
+            let name = pair
+                .path
+                .get_ident()
+                .map(|ident| ident.to_string())
+                .ok_or_else(|| syn::Error::new_spanned(&pair.path, "expected an option name"))?;
+            match pair.value {
+                Expr::Lit(expr_lit) => values.push((name, expr_lit.lit)),
+                other => return Err(syn::Error::new_spanned(other, "expected a literal value")),
             }
-        };
-        let fn_body = &input_fn.block;
-
-        // Generate cache key based on argument names
-        let arg_names: Vec<_> = fn_args
-            .iter()
-            .filter_map(|arg| {
-                if let FnArg::Typed(PatType { pat, .. }) = arg {
-                    Some(quote! { #pat })
-                } else {
-                    None
-                }
-            })
-            .collect();
+        }
+        Ok(Self { values })
+    }
+}
 
-        let generate_key = if arg_names.is_empty() {
-            quote! {
-                let key = String::from("static_key");
-            }
-        } else {
-            quote! {
-                let key = format!("{:?}", (#(#arg_names),*));
+impl CacheArgs {
+    /// Reject options the attribute does not understand.
+    fn check_known(&self, known: &[&str]) -> syn::Result<()> {
+        for (name, lit) in &self.values {
+            if !known.contains(&name.as_str()) {
+                return Err(syn::Error::new_spanned(
+                    lit,
+                    format!("unknown option `{}`, expected one of: {}", name, known.join(", ")),
+                ));
             }
-        };
-
-        // Unique cache variable for this function
-        let cache_name = quote::format_ident!("{}_CACHE", fn_name.to_string().to_uppercase());
+        }
+        Ok(())
+    }
 
-        // Generate the expanded function
-        let expanded = quote! {
-            ::lazy_static::lazy_static! {
-                static ref #cache_name: ::std::sync::Mutex<::cacheForge::LruCache<String, #fn_return_type>> =
-                    ::std::sync::Mutex::new(::cacheForge::LruCache::new(#cache_size));
-            }
+    /// The literal given for an option, if any.
+    fn lit(&self, name: &str) -> Option<&Lit> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, lit)| lit)
+    }
 
-            fn #fn_name(#fn_args) -> #fn_return_type {
-                #generate_key
+    /// An integer option, or `None` if it was not given.
+    fn usize(&self, name: &str) -> syn::Result<Option<usize>> {
+        match self.lit(name) {
+            Some(Lit::Int(lit_int)) => lit_int.base10_parse::<usize>().map(Some),
+            Some(lit) => Err(syn::Error::new_spanned(lit, format!("`{}` must be an integer", name))),
+            None => Ok(None),
+        }
+    }
 
-                // Check the cache
-                {
-                    let mut cache = #cache_name.lock().unwrap();
-                    if let Some(cached) = cache.get(&key) {
-                        return cached.clone();
-                    }
-                }
+    /// A string option, or `None` if it was not given.
+    fn string(&self, name: &str) -> syn::Result<Option<(String, &Lit)>> {
+        match self.lit(name) {
+            Some(lit @ Lit::Str(lit_str)) => Ok(Some((lit_str.value(), lit))),
+            Some(lit) => Err(syn::Error::new_spanned(lit, format!("`{}` must be a string", name))),
+            None => Ok(None),
+        }
+    }
 
-                // Compute the result
-                let result = (|| #fn_body)();
+    /// The eviction policy named by the `policy` option, defaulting to LRU.
+    fn policy(&self) -> syn::Result<Policy> {
+        let Some((name, lit)) = self.string("policy")? else {
+            return Ok(Policy::Lru);
+        };
+        match name.as_str() {
+            "lru" => Ok(Policy::Lru),
+            "fifo" => Ok(Policy::Fifo),
+            "mru" => Ok(Policy::Mru),
+            "random" => Ok(Policy::Random),
+            "lfu" => Ok(Policy::Lfu),
+            "clock" => Ok(Policy::Clock),
+            "slru" => Ok(Policy::Slru),
+            "2q" => Ok(Policy::TwoQueue),
+            _ => Err(syn::Error::new_spanned(
+                lit,
+                format!("unknown policy `{}`, expected one of: lru, fifo, mru, random, lfu, clock, slru, 2q", name),
+            )),
+        }
+    }
+}
 
-                // Store in the cache
-                {
-                    let mut cache = #cache_name.lock().unwrap();
-                    cache.insert(key, result.clone());
-                }
+/// Eviction policies the attributes can select by name.
+#[derive(Clone, Copy, PartialEq)]
+enum Policy {
+    Lru,
+    Fifo,
+    Mru,
+    Random,
+    Lfu,
+    Clock,
+    Slru,
+    TwoQueue,
+}
 
-                result
+impl Policy {
+    /// Lookup, store and resize steps for a cache of `cache_size` entries
+    /// evicting by this policy.
+    fn expansion(self, fn_return_type: &Type, cache_name: &Ident, cache_size: usize) -> CacheExpansion {
+        let policy = match self {
+            Policy::Lru => quote! { ::cacheForge::Lru },
+            Policy::Fifo => quote! { ::cacheForge::Fifo },
+            Policy::Mru => quote! { ::cacheForge::Mru },
+            Policy::Random => quote! { ::cacheForge::Random },
+            Policy::Lfu => quote! { ::cacheForge::Lfu },
+            Policy::Slru => quote! { ::cacheForge::Slru },
+            Policy::TwoQueue => {
+                return locked_cache_expansion(
+                    cache_name,
+                    quote! { ::cacheForge::TwoQueueCache<String, #fn_return_type> },
+                    quote! { ::cacheForge::TwoQueueCache::new(#cache_size) },
+                )
+            }
+            // Hits only take a read lock inside the cache, so no outer mutex
+            Policy::Clock => {
+                return shared_cache_expansion(
+                    cache_name,
+                    quote! { ::cacheForge::ClockCache<String, #fn_return_type> },
+                    quote! { ::cacheForge::ClockCache::new(#cache_size) },
+                )
             }
         };
-
-        TokenStream::from(expanded)
-    } else {
-        TokenStream::from(quote! {
-            compile_error!("The #[cachable] attribute can only be used on functions.");
-        })
+        locked_cache_expansion(
+            cache_name,
+            quote! { ::cacheForge::Cache<String, #fn_return_type, #policy> },
+            quote! { ::cacheForge::Cache::new(#cache_size) },
+        )
     }
 }
 
+/// Pieces of the generated code that differ between the cache attributes.
+struct CacheExpansion {
+    /// Type of the static cache.
+    cache_type: TokenStream2,
+    /// Expression creating the static cache.
+    cache_init: TokenStream2,
+    /// Statement that returns early when `key` is cached.
+    lookup: TokenStream2,
+    /// Statement that stores `result` under `key`.
+    store: TokenStream2,
+    /// Expression resizing the cache to `new_capacity` and returning the
+    /// evicted entries, for caches that can be resized.
+    resize: Option<TokenStream2>,
+}
 
-#[proc_macro_attribute]
-pub fn cachable(attr: TokenStream, item: TokenStream) -> TokenStream {
-    // Parse the attribute to extract the cache size
-    let cache_size = if !attr.is_empty() {
-        let parsed_attr = parse_macro_input!(attr as Meta);
-        match parsed_attr {
-            Meta::NameValue(name_value) => {
-                // Accessing the value as a reference to Expr
-                if let Expr::Lit(expr_lit) = name_value.value {
-                    if let Lit::Int(lit_int) = expr_lit.lit {
-                        lit_int.base10_parse::<usize>().unwrap_or(2) // Default to 2 if parsing fails
-                    } else {
-                        2 // Default to 2 if the value isn't an integer literal
-                    }
-                } else {
-                    2 // Default to 2 if the value isn't a literal expression
-                }
-            }
-            _ => 2, // Default to 2 if no valid size is provided
-        }
-    } else {
-        2 // Default size if no attribute is provided
-    };
-
+/// Parse the function a cache attribute is applied to and wrap its body in
+/// the lookup and store steps produced by `expansion`, which is given the
+/// function's return type and the name of its static cache.
+fn expand_cached_fn(
+    item: TokenStream,
+    expansion: impl FnOnce(&Type, &Ident) -> CacheExpansion,
+) -> TokenStream {
     let input = parse_macro_input!(item as Item);
 
     if let Item::Fn(input_fn) = input {
-        let fn_name = &input_fn.sig.ident;
-        let fn_args = &input_fn.sig.inputs;
         let fn_return_type = match &input_fn.sig.output {
             ReturnType::Type(_, ty) => ty.clone(),
             ReturnType::Default => {
@@ -143,6 +169,8 @@ pub fn cachable(attr: TokenStream, item: TokenStream) -> TokenStream {
                 });
             }
         };
+        let fn_name = &input_fn.sig.ident;
+        let fn_args = &input_fn.sig.inputs;
         let fn_body = &input_fn.block;
 
         // Generate cache key based on argument names
@@ -169,33 +197,41 @@ pub fn cachable(attr: TokenStream, item: TokenStream) -> TokenStream {
 
         // Unique cache variable for this function
         let cache_name = quote::format_ident!("{}_CACHE", fn_name.to_string().to_uppercase());
+        let CacheExpansion { cache_type, cache_init, lookup, store, resize } =
+            expansion(&fn_return_type, &cache_name);
+        let fn_vis = &input_fn.vis;
+
+        // Companion function to resize the cache at runtime
+        let resize_fn = resize.map(|resize| {
+            let resize_name = quote::format_ident!("resize_{}_cache", fn_name);
+            quote! {
+                /// Resize the cache of the memoized function, returning the evicted entries.
+                #[allow(dead_code)]
+                #fn_vis fn #resize_name(new_capacity: usize) -> Vec<(String, #fn_return_type)> {
+                    #resize
+                }
+            }
+        });
 
         // Generate the expanded function
         let expanded = quote! {
             ::lazy_static::lazy_static! {
-                static ref #cache_name: ::std::sync::Mutex<::cacheForge::LruCache<String, #fn_return_type>> =
-                    ::std::sync::Mutex::new(::cacheForge::LruCache::new(#cache_size));
+                static ref #cache_name: #cache_type = #cache_init;
             }
 
-            fn #fn_name(#fn_args) -> #fn_return_type {
+            #resize_fn
+
+            #fn_vis fn #fn_name(#fn_args) -> #fn_return_type {
                 #generate_key
 
                 // Check the cache
-                {
-                    let mut cache = #cache_name.lock().unwrap();
-                    if let Some(cached) = cache.get(&key) {
-                        return cached.clone();
-                    }
-                }
+                #lookup
 
                 // Compute the result
                 let result = (|| #fn_body)();
 
                 // Store in the cache
-                {
-                    let mut cache = #cache_name.lock().unwrap();
-                    cache.insert(key, result.clone());
-                }
+                #store
 
                 result
             }
@@ -209,103 +245,197 @@ pub fn cachable(attr: TokenStream, item: TokenStream) -> TokenStream {
     }
 }
 
-
-#[proc_macro_attribute]
-pub fn expire_cache(attr: TokenStream, item: TokenStream) -> TokenStream {
-    // Parse the attribute to extract the cache size
-    let expire_time = if !attr.is_empty() {
-        let parsed_attr = parse_macro_input!(attr as Meta);
-        match parsed_attr {
-            Meta::NameValue(name_value) => {
-                // Accessing the value as a reference to Expr
-                if let Expr::Lit(expr_lit) = name_value.value {
-                    if let Lit::Int(lit_int) = expr_lit.lit {
-                        lit_int.base10_parse::<usize>().unwrap_or(2) // Default to 2 if parsing fails
-                    } else {
-                        2 // Default to 2 if the value isn't an integer literal
-                    }
-                } else {
-                    2 // Default to 2 if the value isn't a literal expression
-                }
+/// Lookup, store and resize steps for a cache behind a `std::sync::Mutex`.
+fn locked_cache_expansion(cache_name: &Ident, cache_type: TokenStream2, cache_init: TokenStream2) -> CacheExpansion {
+    let lookup = quote! {
+        {
+            let mut cache = #cache_name.lock().unwrap();
+            if let Some(cached) = cache.get(&key) {
+                return cached.clone();
             }
-            _ => 2, // Default to 2 if no valid size is provided
         }
-    } else {
-        2 // Default size if no attribute is provided
     };
+    let store = quote! {
+        {
+            let mut cache = #cache_name.lock().unwrap();
+            cache.insert(key, result.clone());
+        }
+    };
+    CacheExpansion {
+        cache_type: quote! { ::std::sync::Mutex<#cache_type> },
+        cache_init: quote! { ::std::sync::Mutex::new(#cache_init) },
+        lookup,
+        store,
+        resize: Some(quote! { #cache_name.lock().unwrap().resize(new_capacity) }),
+    }
+}
 
-    let input = parse_macro_input!(item as Item);
-
-    if let Item::Fn(input_fn) = input {
-        let fn_name = &input_fn.sig.ident;
-        let fn_args = &input_fn.sig.inputs;
-        let fn_return_type = match &input_fn.sig.output {
-            ReturnType::Type(_, ty) => ty.clone(),
-            ReturnType::Default => {
-                return TokenStream::from(quote! {
-                    compile_error!("Functions with #[cachable] must have a return type.");
-                });
+/// Lookup, store and resize steps for a cache that locks internally and is
+/// used through `&self`.
+fn shared_cache_expansion(cache_name: &Ident, cache_type: TokenStream2, cache_init: TokenStream2) -> CacheExpansion {
+    CacheExpansion {
+        cache_type,
+        cache_init,
+        lookup: quote! {
+            if let Some(cached) = #cache_name.get(&key) {
+                return cached;
             }
-        };
-        let fn_body = &input_fn.block;
+        },
+        store: quote! {
+            #cache_name.insert(key, result.clone());
+        },
+        resize: Some(quote! { #cache_name.resize(new_capacity) }),
+    }
+}
 
-        // Generate cache key based on argument names
-        let arg_names: Vec<_> = fn_args
-            .iter()
-            .filter_map(|arg| {
-                if let FnArg::Typed(PatType { pat, .. }) = arg {
-                    Some(quote! { #pat })
-                } else {
-                    None
-                }
-            })
-            .collect();
+/// Memoize a function in an LRU cache.
+///
+/// Options:
+/// - `size = N`: number of entries to keep (default 2).
+/// - `shards = N`: split the cache into `N` independently locked shards
+///   instead of guarding it with one global mutex, for functions called
+///   from many threads at once.
+/// - `policy = "..."`: evict by `"lru"` (default), `"fifo"`, `"mru"`,
+///   `"random"`, `"lfu"`, `"clock"`, `"slru"` or `"2q"` instead. `"clock"`
+///   suits functions that mostly hit, as hits only take a shared lock;
+///   `"slru"` and `"2q"` keep repeatedly used results through scans of
+///   one-off arguments. Sharded caches only support LRU.
+///
+/// Also generates `resize_<name>_cache(new_capacity)`, which changes the
+/// cache size at runtime and returns the evicted entries.
+#[proc_macro_attribute]
+pub fn lru_cache(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as CacheArgs);
+    let options = args.check_known(&["size", "shards", "policy"]).and_then(|_| {
+        let policy = args.policy()?;
+        let shards = args.usize("shards")?;
+        if shards.is_some() && policy != Policy::Lru {
+            let (_, lit) = args.string("policy")?.expect("policy was given");
+            return Err(syn::Error::new_spanned(lit, "sharded caches only support the `lru` policy"));
+        }
+        Ok((args.usize("size")?.unwrap_or(2), shards, policy))
+    });
+    let (cache_size, shards, policy) = match options {
+        Ok(options) => options,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
-        let generate_key = if arg_names.is_empty() {
-            quote! {
-                let key = String::from("static_key");
-            }
-        } else {
-            quote! {
-                let key = format!("{:?}", (#(#arg_names),*));
-            }
-        };
+    expand_cached_fn(item, |fn_return_type, cache_name| match shards {
+        Some(shards) => shared_cache_expansion(
+            cache_name,
+            quote! { ::cacheForge::ShardedLruCache<String, #fn_return_type> },
+            quote! { ::cacheForge::ShardedLruCache::with_shards(#cache_size, #shards) },
+        ),
+        None => policy.expansion(fn_return_type, cache_name, cache_size),
+    })
+}
 
-        // Unique cache variable for this function
-        let cache_name = quote::format_ident!("{}_CACHE", fn_name.to_string().to_uppercase());
+/// Memoize a function in an LFU cache, which keeps the most frequently
+/// called argument lists even when many one-off calls come in between.
+///
+/// Options:
+/// - `size = N`: number of entries to keep (default 2).
+///
+/// Also generates `resize_<name>_cache(new_capacity)`, like `#[lru_cache]`.
+#[proc_macro_attribute]
+pub fn lfu_cache(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as CacheArgs);
+    let cache_size = match args.check_known(&["size"]).and_then(|_| args.usize("size")) {
+        Ok(size) => size.unwrap_or(2),
+        Err(err) => return err.to_compile_error().into(),
+    };
 
-        // Generate the expanded function
-        let expanded = quote! {
-            ::lazy_static::lazy_static! {
-                static ref #cache_name: ::std::sync::Mutex<::cacheForge::ExpireCache<String, #fn_return_type>> =
-                    ::std::sync::Mutex::new(::cacheForge::ExpireCache::new());
-            }
+    expand_cached_fn(item, |fn_return_type, cache_name| {
+        Policy::Lfu.expansion(fn_return_type, cache_name, cache_size)
+    })
+}
 
-            fn #fn_name(#fn_args) -> #fn_return_type {
-                #generate_key
 
-                {
-                    let mut cache = #cache_name.lock().unwrap();
-                    if let Some(cached) = cache.get(&key) {
-                        return cached.clone();
-                    }
-                }
+#[proc_macro_attribute]
+pub fn cachable(attr: TokenStream, item: TokenStream) -> TokenStream {
+    // Parse the attribute to extract the cache size and policy
+    let args = parse_macro_input!(attr as CacheArgs);
+    let options = args
+        .check_known(&["size", "policy"])
+        .and_then(|_| Ok((args.usize("size")?.unwrap_or(2), args.policy()?)));
+    let (cache_size, policy) = match options {
+        Ok(options) => options, // Size defaults to 2 and policy to LRU
+        Err(err) => return err.to_compile_error().into(),
+    };
 
-                let result = (|| #fn_body)();
+    expand_cached_fn(item, |fn_return_type, cache_name| {
+        policy.expansion(fn_return_type, cache_name, cache_size)
+    })
+}
 
+
+/// Memoize a function, forgetting each result some time after it was computed.
+///
+/// Options:
+/// - `ttl = N`: seconds each result stays cached (default 2).
+/// - `size = N`: keep at most `N` results. When full, expired results are
+///   dropped first, then the least recently used one. Without it the cache
+///   is unbounded and only shrinks as results expire. Requires `ttl`.
+///
+/// Note: `size` used to be the TTL in seconds. It now bounds the number of
+/// results, so it must be given together with an explicit `ttl`; an old
+/// `#[expire_cache(size = 30)]` is a compile error rather than silently
+/// changing meaning, and becomes `#[expire_cache(ttl = 30)]`.
+///
+/// With `size`, also generates `resize_<name>_cache(new_capacity)`, which
+/// changes the cache size at runtime and returns the evicted entries.
+#[proc_macro_attribute]
+pub fn expire_cache(attr: TokenStream, item: TokenStream) -> TokenStream {
+    // Parse the attribute to extract the time-to-live in seconds and the size
+    let args = parse_macro_input!(attr as CacheArgs);
+    let options = args
+        .check_known(&["size", "ttl"])
+        .and_then(|_| {
+            let (ttl, size) = (args.usize("ttl")?, args.usize("size")?);
+            if let (Some(size_lit), None) = (args.lit("size"), ttl) {
+                return Err(syn::Error::new_spanned(
+                    size_lit,
+                    "`size` is the number of results to keep and needs an explicit `ttl`; \
+                     for the old meaning of `size` as the TTL in seconds, use `ttl` instead",
+                ));
+            }
+            Ok((ttl.unwrap_or(2) as u64, size))
+        });
+    let (ttl_secs, cache_size) = match options {
+        Ok(options) => options, // TTL defaults to 2 seconds and size to unbounded
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let ttl = quote! { ::std::time::Duration::from_secs(#ttl_secs) };
+
+    expand_cached_fn(item, |fn_return_type, cache_name| match cache_size {
+        Some(cache_size) => {
+            let mut expansion = locked_cache_expansion(
+                cache_name,
+                quote! { ::cacheForge::TtlLruCache<String, #fn_return_type> },
+                quote! { ::cacheForge::TtlLruCache::with_default_ttl(#cache_size, #ttl) },
+            );
+            expansion.store = quote! {
                 {
                     let mut cache = #cache_name.lock().unwrap();
-                    cache.insert(key, result.clone(), #expire_time);
+                    // A TTL too long to represent leaves the result uncached
+                    let _ = cache.insert_default(key, result.clone());
                 }
-
-                result
-            }
-        };
-
-        TokenStream::from(expanded)
-    } else {
-        TokenStream::from(quote! {
-            compile_error!("The #[cachable] attribute can only be used on functions.");
-        })
-    }
+            };
+            expansion
+        }
+        None => {
+            // ExpireCache locks internally, so hits share a read lock
+            let mut expansion = shared_cache_expansion(
+                cache_name,
+                quote! { ::cacheForge::ExpireCache<String, #fn_return_type> },
+                quote! { ::cacheForge::ExpireCache::with_default_ttl(#ttl) },
+            );
+            expansion.store = quote! {
+                // A TTL too long to represent leaves the result uncached
+                let _ = #cache_name.insert_default(key, result.clone());
+            };
+            expansion.resize = None;
+            expansion
+        }
+    })
 }
@@ -0,0 +1,597 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::bounded_cache::BoundedCache;
+use crate::listener::{RemovalCause, RemovalQueue};
+use crate::lookup::Lookup;
+use crate::slab::{Link, List, Slab};
+
+/// Adaptive Replacement Cache (ARC).
+///
+/// Resident entries are split between T1, holding keys seen once recently,
+/// and T2, holding keys seen at least twice. The keys of entries evicted
+/// from each are remembered in the ghost lists B1 and B2. A miss that hits
+/// a ghost list shows which side was evicted too early, and shifts the
+/// target size of T1 towards it. One-off keys only ever pass through T1,
+/// so a large scan cannot flush the frequently used entries out of T2.
+pub struct ArcCache<K, V> {
+    map: HashMap<K, Slot>,
+    entries: Slab<Node<K, V>>,
+    entry_links: Vec<Link>,
+    ghosts: Slab<K>,
+    ghost_links: Vec<Link>,
+    t1: List,
+    t2: List,
+    b1: List,
+    b2: List,
+    /// Target size of T1.
+    target: usize,
+    capacity: usize,
+    removals: RemovalQueue<K, V>,
+}
+
+/// Which list a key is on, and its index in the matching slab.
+#[derive(Clone, Copy)]
+enum Slot {
+    Recent(usize),
+    Frequent(usize),
+    RecentGhost(usize),
+    FrequentGhost(usize),
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ArcCache<K, V> {
+    /// Create a new ARC cache holding at most `capacity` entries. It also
+    /// remembers up to `capacity` keys of evicted entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            entries: Slab::new(),
+            entry_links: Vec::new(),
+            ghosts: Slab::new(),
+            ghost_links: Vec::new(),
+            t1: List::new(),
+            t2: List::new(),
+            b1: List::new(),
+            b2: List::new(),
+            target: 0,
+            capacity,
+            removals: RemovalQueue::new(),
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.removals.set_listener(Arc::new(listener));
+        self
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.t1.len() + self.t2.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of entries the cache currently aims to give to keys seen
+    /// only once; the rest of the capacity goes to keys seen again.
+    pub fn recent_target(&self) -> usize {
+        self.target
+    }
+
+    /// Returns `true` if the key is cached, without marking it as used.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        matches!(self.map.get(key), Some(Slot::Recent(_) | Slot::Frequent(_)))
+    }
+
+    /// Get a reference to a value without marking it as used.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match *self.map.get(key)? {
+            Slot::Recent(idx) | Slot::Frequent(idx) => Some(&self.entries.get(idx).value),
+            Slot::RecentGhost(_) | Slot::FrequentGhost(_) => None,
+        }
+    }
+
+    /// Get a mutable reference to a value without marking it as used.
+    pub fn peek_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match *self.map.get(key)? {
+            Slot::Recent(idx) | Slot::Frequent(idx) => Some(&mut self.entries.get_mut(idx).value),
+            Slot::RecentGhost(_) | Slot::FrequentGhost(_) => None,
+        }
+    }
+
+    /// Iterate over the entries without marking them as used: the
+    /// frequently used side first, then the recently used one, each from
+    /// the most to the least recently used entry.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.walk(&self.t2).chain(self.walk(&self.t1)).map(|idx| {
+            let node = self.entries.get(idx);
+            (&node.key, &node.value)
+        })
+    }
+
+    /// Iterate over the keys in the order of `iter`.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// Iterate over the values in the order of `iter`.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Get the value for `key`, computing and inserting it with `f` if it is
+    /// not cached. The result tells whether the value was a hit or a miss.
+    pub fn get_or_insert_with<F>(&mut self, key: K, f: F) -> Lookup<V>
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(value) = self.get(&key) {
+            return Lookup::Hit(value);
+        }
+        let value = f();
+        self.insert(key, value.clone());
+        Lookup::Miss(value)
+    }
+
+    /// Get a value associated with a key, moving it to the frequently used side.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.map.get_mut(key)?;
+        let idx = match *slot {
+            Slot::Recent(idx) => {
+                self.t1.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::Frequent(idx) => {
+                self.t2.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::RecentGhost(_) | Slot::FrequentGhost(_) => return None,
+        };
+        *slot = Slot::Frequent(idx);
+        self.t2.push_front(&mut self.entry_links, idx);
+        Some(self.entries.get(idx).value.clone())
+    }
+
+    /// Insert a key-value pair into the cache.
+    ///
+    /// Updating a cached key counts as a second use. A key that was evicted
+    /// recently goes straight to the frequently used side and adapts the
+    /// split between the two sides.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        match self.map.get(&key).copied() {
+            Some(Slot::Recent(idx)) | Some(Slot::Frequent(idx)) => {
+                self.get(&key);
+                let old_value = std::mem::replace(&mut self.entries.get_mut(idx).value, value);
+                self.removals.record(key, old_value, RemovalCause::Replaced);
+            }
+            Some(Slot::RecentGhost(ghost)) => {
+                // T1 was too small: grow its target
+                let step = (self.b2.len() / self.b1.len()).max(1);
+                self.target = (self.target + step).min(self.capacity);
+                self.b1.unlink(&mut self.ghost_links, ghost);
+                self.ghosts.remove(ghost);
+                self.replace(false);
+                self.admit(key, value, true);
+            }
+            Some(Slot::FrequentGhost(ghost)) => {
+                // T2 was too small: shrink the target of T1
+                let step = (self.b1.len() / self.b2.len()).max(1);
+                self.target = self.target.saturating_sub(step);
+                self.b2.unlink(&mut self.ghost_links, ghost);
+                self.ghosts.remove(ghost);
+                self.replace(true);
+                self.admit(key, value, true);
+            }
+            None => {
+                let recent = self.t1.len() + self.b1.len();
+                if recent >= self.capacity {
+                    if self.t1.len() < self.capacity {
+                        self.forget_oldest(false);
+                        self.replace(false);
+                    } else {
+                        // T1 fills the whole cache, so drop its oldest
+                        // entry without remembering it
+                        let idx = self.t1.back().expect("T1 is full");
+                        self.t1.unlink(&mut self.entry_links, idx);
+                        let node = self.entries.remove(idx);
+                        self.map.remove(&node.key);
+                        self.removals.record(node.key, node.value, RemovalCause::Evicted);
+                    }
+                } else {
+                    let total = recent + self.t2.len() + self.b2.len();
+                    if total >= self.capacity {
+                        if total >= 2 * self.capacity {
+                            self.forget_oldest(true);
+                        }
+                        self.replace(false);
+                    }
+                }
+                self.admit(key, value, false);
+            }
+        }
+        self.removals.notify();
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = match *self.map.get(key)? {
+            Slot::Recent(idx) => {
+                self.t1.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::Frequent(idx) => {
+                self.t2.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::RecentGhost(_) | Slot::FrequentGhost(_) => return None,
+        };
+        let node = self.entries.remove(idx);
+        self.map.remove(key);
+        self.removals.record_cloned(node.key, &node.value, RemovalCause::Explicit);
+        self.removals.notify();
+        Some(node.value)
+    }
+
+    /// Remove every entry, reporting each as `RemovalCause::Cleared`, and
+    /// forget the evicted keys.
+    pub fn clear(&mut self) {
+        for list in [&mut self.t1, &mut self.t2] {
+            while let Some(idx) = list.back() {
+                list.unlink(&mut self.entry_links, idx);
+                let node = self.entries.remove(idx);
+                self.removals.record(node.key, node.value, RemovalCause::Cleared);
+            }
+        }
+        for list in [&mut self.b1, &mut self.b2] {
+            while let Some(idx) = list.back() {
+                list.unlink(&mut self.ghost_links, idx);
+                self.ghosts.remove(idx);
+            }
+        }
+        self.map.clear();
+        self.target = 0;
+        self.removals.notify();
+    }
+
+    /// Change the capacity, keeping the cached entries where possible.
+    ///
+    /// When shrinking, entries are evicted the way inserts evict them until
+    /// the rest fit, and the ghost lists are trimmed to the new size. The
+    /// evicted entries are reported to the removal listener as
+    /// `RemovalCause::Evicted` and also returned, in eviction order.
+    pub fn resize(&mut self, new_capacity: usize) -> Vec<(K, V)> {
+        self.capacity = new_capacity;
+        self.target = self.target.min(new_capacity);
+        self.removals.collect_evictions();
+        while self.len() > new_capacity {
+            self.replace(false);
+        }
+        while self.t1.len() + self.b1.len() > new_capacity && !self.b1.is_empty() {
+            self.forget_oldest(false);
+        }
+        while self.len() + self.b1.len() + self.b2.len() > 2 * new_capacity && !self.b2.is_empty() {
+            self.forget_oldest(true);
+        }
+        let evicted = self.removals.take_evictions();
+        self.removals.notify();
+        evicted
+    }
+
+    /// The entry slots of `list`, from front to back.
+    fn walk<'a>(&'a self, list: &'a List) -> impl Iterator<Item = usize> + 'a {
+        std::iter::successors(list.front(), move |&idx| list.next(&self.entry_links, idx))
+    }
+
+    /// Store a new entry at the front of T1, or of T2 if `frequent`.
+    fn admit(&mut self, key: K, value: V, frequent: bool) {
+        let idx = self.entries.insert(Node {
+            key: key.clone(),
+            value,
+        });
+        let slot = if frequent {
+            self.t2.push_front(&mut self.entry_links, idx);
+            Slot::Frequent(idx)
+        } else {
+            self.t1.push_front(&mut self.entry_links, idx);
+            Slot::Recent(idx)
+        };
+        self.map.insert(key, slot);
+    }
+
+    /// If the cache is full, evict the oldest entry of T1 or T2, depending on
+    /// how T1 compares to its target, and remember its key.
+    fn replace(&mut self, frequent_ghost_hit: bool) {
+        if self.len() < self.capacity {
+            return;
+        }
+        let t1_len = self.t1.len();
+        let from_recent = t1_len > 0
+            && (t1_len > self.target
+                || (frequent_ghost_hit && t1_len == self.target)
+                || self.t2.is_empty());
+        let (list, ghosts) = if from_recent {
+            (&mut self.t1, &mut self.b1)
+        } else {
+            (&mut self.t2, &mut self.b2)
+        };
+        let Some(idx) = list.back() else {
+            return;
+        };
+        list.unlink(&mut self.entry_links, idx);
+        let node = self.entries.remove(idx);
+        let ghost = self.ghosts.insert(node.key.clone());
+        ghosts.push_front(&mut self.ghost_links, ghost);
+        let slot = if from_recent {
+            Slot::RecentGhost(ghost)
+        } else {
+            Slot::FrequentGhost(ghost)
+        };
+        *self.map.get_mut(&node.key).expect("resident key is mapped") = slot;
+        self.removals.record(node.key, node.value, RemovalCause::Evicted);
+    }
+
+    /// Drop the oldest remembered key of B1, or of B2 if `frequent`.
+    fn forget_oldest(&mut self, frequent: bool) {
+        let list = if frequent { &mut self.b2 } else { &mut self.b1 };
+        if let Some(idx) = list.back() {
+            list.unlink(&mut self.ghost_links, idx);
+            let key = self.ghosts.remove(idx);
+            self.map.remove(&key);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> BoundedCache<K, V> for ArcCache<K, V> {
+    fn insert(&mut self, key: K, value: V) {
+        ArcCache::insert(self, key, value)
+    }
+
+    fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        ArcCache::get(self, key)
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        ArcCache::remove(self, key)
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        ArcCache::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        ArcCache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        ArcCache::capacity(self)
+    }
+}
+
+impl<K, V> fmt::Debug for ArcCache<K, V>
+where
+    K: Hash + Eq + Clone + fmt::Debug,
+    V: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Extend<(K, V)> for ArcCache<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Collects into a cache just large enough for every distinct key.
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for ArcCache<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let items: Vec<(K, V)> = iter.into_iter().collect();
+        let distinct = items.iter().map(|(key, _)| key).collect::<HashSet<_>>().len();
+        let mut cache = Self::new(distinct);
+        cache.extend(items);
+        cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounded_cache::testing::touch;
+    use crate::lru_cache::LruCache;
+    use std::sync::Mutex;
+
+    /// Warm up a hot set, sweep a long run of one-off keys, then count how
+    /// many hot keys are still cached.
+    fn hot_keys_after_scan<C: BoundedCache<u32, u32>>(cache: &mut C) -> usize {
+        for _ in 0..3 {
+            for hot in 0..50 {
+                touch(cache, hot);
+            }
+        }
+        for cold in 1_000..3_000 {
+            touch(cache, cold);
+        }
+        (0..50).filter(|hot| touch(cache, *hot)).count()
+    }
+
+    #[test]
+    fn arc_cache_works() {
+        let mut cache = ArcCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.len(), 2);
+
+        cache.insert("c", 3);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains_key("a"));
+        assert_eq!(cache.remove("a"), Some(1));
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn zero_capacity_stores_nothing() {
+        let mut cache = ArcCache::new(0);
+        cache.insert(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn resists_scans_where_lru_does_not() {
+        let mut lru = LruCache::new(100);
+        let mut arc = ArcCache::new(100);
+        assert_eq!(hot_keys_after_scan(&mut lru), 0);
+        assert_eq!(hot_keys_after_scan(&mut arc), 50);
+    }
+
+    #[test]
+    fn ghost_hits_adapt_the_target() {
+        let mut cache = ArcCache::new(2);
+        cache.insert(1, 1);
+        cache.get(&1);
+        cache.insert(2, 2);
+        // T1 is over its target of 0, so 2 is evicted and remembered in B1
+        cache.insert(3, 3);
+        assert!(!cache.contains_key(&2));
+        assert_eq!(cache.recent_target(), 0);
+
+        // Seeing 2 again means T1 was too small
+        cache.insert(2, 2);
+        assert_eq!(cache.recent_target(), 1);
+        assert!(cache.contains_key(&2));
+        assert!(cache.contains_key(&3));
+        assert!(!cache.contains_key(&1));
+
+        // And seeing 1 again, evicted from T2, swings it back
+        cache.insert(1, 1);
+        assert_eq!(cache.recent_target(), 0);
+    }
+
+    #[test]
+    fn ghost_lists_stay_bounded() {
+        let mut cache = ArcCache::new(10);
+        for key in 0..1_000 {
+            touch(&mut cache, key % 37);
+            touch(&mut cache, key);
+        }
+        assert!(cache.len() <= 10);
+        assert!(cache.b1.len() + cache.b2.len() <= 10);
+        assert!(cache.map.len() <= 20);
+    }
+
+    #[test]
+    fn removal_listener_reports_causes() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = ArcCache::new(1)
+            .with_removal_listener(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+
+        cache.insert(1, "one");
+        cache.insert(1, "uno");
+        cache.insert(2, "two");
+        cache.remove(&2);
+        cache.insert(3, "three");
+        cache.clear();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (1, "one", RemovalCause::Replaced),
+                (1, "uno", RemovalCause::Evicted),
+                (2, "two", RemovalCause::Explicit),
+                (3, "three", RemovalCause::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn resize_evicts_and_forgets_ghosts() {
+        let mut cache = ArcCache::new(4);
+        for key in 1..=4 {
+            cache.insert(key, key);
+        }
+        cache.get(&4);
+        assert_eq!(cache.resize(2), vec![(1, 1), (2, 2)]);
+        assert_eq!(cache.capacity(), 2);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 3]);
+        assert!(cache.b1.len() <= 2);
+
+        assert_eq!(cache.resize(0), vec![(3, 3), (4, 4)]);
+        assert!(cache.is_empty());
+        assert!(cache.map.is_empty());
+        cache.insert(5, 5);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn stands_in_for_lru_cache() {
+        let mut cache: ArcCache<_, _> = [(1, "one"), (2, "two"), (1, "uno")].into_iter().collect();
+        assert_eq!(cache.capacity(), 2);
+        assert_eq!(format!("{:?}", cache), r#"{1: "uno", 2: "two"}"#);
+
+        *cache.peek_mut(&2).unwrap() = "dos";
+        assert_eq!(cache.get_or_insert_with(2, || "zwei"), Lookup::Hit("dos"));
+        assert_eq!(cache.get_or_insert_with(3, || "tres"), Lookup::Miss("tres"));
+        cache.extend([(4, "cuatro")]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.iter().count(), 2);
+        assert!(cache.contains_key(&4));
+    }
+}
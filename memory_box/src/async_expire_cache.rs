@@ -0,0 +1,317 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+
+use crate::clock::{Clock, TokioClock};
+use crate::error::TtlError;
+use crate::expire_cache::{deadline_after, Entries, Now};
+use crate::in_flight::InFlight;
+use crate::lookup::Lookup;
+
+/// [`ExpireCache`](crate::ExpireCache) for async code, shared between tasks
+/// by reference.
+///
+/// The entries sit behind a Tokio lock, so a task waiting for it yields to
+/// the executor instead of blocking its thread. Live keys are read under a
+/// shared lock.
+///
+/// Time is read from Tokio's clock by default, so tests can pause and
+/// advance it.
+pub struct AsyncExpireCache<K, V, C = TokioClock> {
+    entries: RwLock<Entries<K, V>>,
+    default_ttl: Option<Duration>,
+    clock: C,
+    in_flight: InFlight<K, V>,
+}
+
+impl<K, V, C> Default for AsyncExpireCache<K, V, C>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    C: Clock + Default,
+{
+    fn default() -> Self {
+        Self::with_clock(C::default())
+    }
+}
+
+impl<K, V> AsyncExpireCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates a new `AsyncExpireCache` reading Tokio's clock.
+    pub fn new() -> Self {
+        Self::with_clock(TokioClock)
+    }
+
+    /// Creates a new `AsyncExpireCache` whose entries live for `ttl` unless
+    /// inserted with a TTL of their own.
+    pub fn with_default_ttl(ttl: Duration) -> Self {
+        Self::with_default_ttl_and_clock(ttl, TokioClock)
+    }
+}
+
+impl<K, V, C> AsyncExpireCache<K, V, C>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    C: Clock,
+{
+    /// Creates a new `AsyncExpireCache` reading the time from `clock`.
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            entries: RwLock::new(Entries::new(clock.now())),
+            default_ttl: None,
+            clock,
+            in_flight: InFlight::new(),
+        }
+    }
+
+    /// Creates a new `AsyncExpireCache` with a default TTL, reading the time
+    /// from `clock`.
+    pub fn with_default_ttl_and_clock(ttl: Duration, clock: C) -> Self {
+        Self {
+            default_ttl: Some(ttl),
+            ..Self::with_clock(clock)
+        }
+    }
+
+    /// Also expire entries that go `idle` without being read. The TTL an
+    /// entry was inserted with still caps its total lifetime.
+    pub fn with_time_to_idle(mut self, idle: Duration) -> Self {
+        self.entries.get_mut().time_to_idle = Some(idle);
+        self
+    }
+
+    /// The TTL used by `insert_default`, if one was set.
+    pub fn default_ttl(&self) -> Option<Duration> {
+        self.default_ttl
+    }
+
+    /// Inserts a key-value pair that lives for `ttl`. A zero TTL stores a
+    /// value that has already expired.
+    ///
+    /// Fails without changing the cache if the TTL is too long to represent.
+    pub async fn insert(&self, key: K, value: V, ttl: Duration) -> Result<(), TtlError> {
+        let now = Now::of(&self.clock);
+        let deadline = deadline_after(now.instant, ttl)?;
+        let mut entries = self.entries.write().await;
+        entries.insert(key, value, ttl, deadline, now);
+        entries.remove_expired(now);
+        Ok(())
+    }
+
+    /// Inserts a key-value pair that lives for the default TTL.
+    ///
+    /// Fails with `TtlError::NoDefault` if no default TTL was set.
+    pub async fn insert_default(&self, key: K, value: V) -> Result<(), TtlError> {
+        let ttl = self.default_ttl.ok_or(TtlError::NoDefault)?;
+        self.insert(key, value, ttl).await
+    }
+
+    /// Retrieves the value associated with a key, if it has not expired.
+    /// Under a time-to-idle this counts as a use of the entry.
+    pub async fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = Now::of(&self.clock);
+        {
+            let entries = self.entries.read().await;
+            let entry = entries.map.get(key)?;
+            if entries.expiry(entry, now) > now.instant {
+                entries.touch(entry, now);
+                return Some(entry.value.clone());
+            }
+        }
+        self.entries.write().await.remove_expired(now);
+        None
+    }
+
+    /// Returns `true` if the key has a live value, without counting as a
+    /// read of it.
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = Now::of(&self.clock);
+        self.entries.read().await.live(key, now).is_some()
+    }
+
+    /// Remove a key from the cache, returning its value if it had not
+    /// expired.
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = Now::of(&self.clock);
+        let mut entries = self.entries.write().await;
+        let (_, entry) = entries.remove(key)?;
+        (entries.expiry(&entry, now) > now.instant).then_some(entry.value)
+    }
+
+    /// Remove every entry.
+    pub async fn clear(&self) {
+        self.entries.write().await.clear();
+    }
+
+    /// Number of live entries. Expired entries are removed first.
+    pub async fn len(&self) -> usize {
+        let now = Now::of(&self.clock);
+        let mut entries = self.entries.write().await;
+        entries.remove_expired(now);
+        entries.map.len()
+    }
+
+    /// Returns `true` if the cache holds no live entries.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Get the value for `key`, computing it with `f` and inserting it for
+    /// `ttl` if it is not cached or has expired. The result tells whether
+    /// the value was a hit or a miss.
+    ///
+    /// The cache is not locked while `f` runs, and the TTL starts once the
+    /// value is ready. Concurrent calls for the same key wait for one
+    /// computation and get its value as a hit.
+    ///
+    /// Fails before running `f` if the TTL is too long to represent.
+    pub async fn get_or_insert_with<F>(&self, key: K, ttl: Duration, f: F) -> Result<Lookup<V>, TtlError>
+    where
+        F: AsyncFnOnce() -> V,
+    {
+        deadline_after(self.clock.now(), ttl)?;
+        if let Some(value) = self.get(&key).await {
+            return Ok(Lookup::Hit(value));
+        }
+        let lookup = self
+            .in_flight
+            .load(&key, || async {
+                // Another call may have inserted it since the check above
+                if let Some(value) = self.get(&key).await {
+                    return Lookup::Hit(value);
+                }
+                let value = f().await;
+                // Checked above; only a computation running for centuries
+                // could leave the value unstored
+                let _ = self.insert(key.clone(), value.clone(), ttl).await;
+                Lookup::Miss(value)
+            })
+            .await;
+        Ok(lookup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::time::{advance, sleep};
+
+    const FIVE_SECONDS: Duration = Duration::from_secs(5);
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_expire_with_tokio_time() {
+        let cache = AsyncExpireCache::new();
+        cache.insert("key1", "value1", FIVE_SECONDS).await.unwrap();
+        cache.insert("key2", "value2", Duration::from_secs(3)).await.unwrap();
+
+        advance(Duration::from_secs(2)).await;
+        assert_eq!(cache.get("key1").await, Some("value1"));
+        assert_eq!(cache.get("key2").await, Some("value2"));
+
+        advance(Duration::from_secs(2)).await;
+        assert_eq!(cache.get("key1").await, Some("value1"));
+        assert_eq!(cache.get("key2").await, None);
+        assert_eq!(cache.len().await, 1);
+
+        advance(Duration::from_secs(2)).await;
+        assert!(!cache.contains_key("key1").await);
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn remove_and_default_ttl() {
+        let cache = AsyncExpireCache::with_default_ttl(FIVE_SECONDS);
+        cache.insert_default("live", 1).await.unwrap();
+        cache.insert("expired", 2, Duration::from_secs(1)).await.unwrap();
+        advance(Duration::from_secs(1)).await;
+
+        assert_eq!(cache.remove("live").await, Some(1));
+        assert_eq!(cache.remove("expired").await, None);
+        assert_eq!(
+            AsyncExpireCache::<&str, i32>::new().insert_default("key", 1).await,
+            Err(TtlError::NoDefault)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reads_keep_idle_entries_alive() {
+        let cache = AsyncExpireCache::new().with_time_to_idle(Duration::from_secs(2));
+        cache.insert("read", 1, FIVE_SECONDS).await.unwrap();
+        cache.insert("idle", 2, FIVE_SECONDS).await.unwrap();
+        for _ in 0..3 {
+            advance(Duration::from_secs(1)).await;
+            assert_eq!(cache.get("read").await, Some(1));
+        }
+        assert_eq!(cache.get("idle").await, None);
+        advance(Duration::from_secs(2)).await;
+        assert_eq!(cache.get("read").await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_loads_of_a_key_run_once() {
+        let cache = Arc::new(AsyncExpireCache::new());
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let loads = Arc::clone(&loads);
+                tokio::spawn(async move {
+                    cache
+                        .get_or_insert_with("key", FIVE_SECONDS, async || {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            sleep(Duration::from_secs(1)).await;
+                            42
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        let mut misses = 0;
+        for task in tasks {
+            let lookup = task.await.unwrap();
+            assert_eq!(*lookup.value(), 42);
+            misses += usize::from(lookup.is_miss());
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(misses, 1);
+
+        // The TTL started once the value was ready
+        advance(Duration::from_secs(4)).await;
+        assert_eq!(cache.get("key").await, Some(42));
+        advance(Duration::from_secs(1)).await;
+        let reload = cache.get_or_insert_with("key", FIVE_SECONDS, async || 7).await;
+        assert_eq!(reload, Ok(Lookup::Miss(7)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unrepresentable_ttls_fail_before_loading() {
+        let cache = AsyncExpireCache::new();
+        let result = cache
+            .get_or_insert_with("key", Duration::MAX, async || unreachable!("not loaded"))
+            .await;
+        assert_eq!(result, Err(TtlError::TooLong(Duration::MAX)));
+        assert_eq!(cache.insert("key", 1, Duration::MAX).await, Err(TtlError::TooLong(Duration::MAX)));
+    }
+}
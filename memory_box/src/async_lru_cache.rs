@@ -0,0 +1,171 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use tokio::sync::Mutex;
+
+use crate::in_flight::InFlight;
+use crate::lookup::Lookup;
+use crate::lru_cache::LruCache;
+
+/// [`LruCache`] for async code, shared between tasks by reference.
+///
+/// The cache sits behind a Tokio mutex, so a task waiting for it yields to
+/// the executor instead of blocking its thread.
+pub struct AsyncLruCache<K, V> {
+    cache: Mutex<LruCache<K, V>>,
+    in_flight: InFlight<K, V>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> AsyncLruCache<K, V> {
+    /// Create a new cache with a given capacity.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(capacity)),
+            in_flight: InFlight::new(),
+        }
+    }
+
+    /// Number of entries in the cache.
+    pub async fn len(&self) -> usize {
+        self.cache.lock().await.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub async fn is_empty(&self) -> bool {
+        self.cache.lock().await.is_empty()
+    }
+
+    /// Returns `true` if the key is cached, without marking it as used.
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.lock().await.contains_key(key)
+    }
+
+    /// Get a value associated with a key, marking it as most recently used.
+    pub async fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.lock().await.get(key)
+    }
+
+    /// Insert a key-value pair, evicting the least recently used entry if
+    /// the cache is full.
+    pub async fn insert(&self, key: K, value: V) {
+        self.cache.lock().await.insert(key, value);
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.lock().await.remove(key)
+    }
+
+    /// Remove every entry.
+    pub async fn clear(&self) {
+        self.cache.lock().await.clear();
+    }
+
+    /// Get the value for `key`, computing it with `f` and inserting it if it
+    /// is not cached. The result tells whether the value was a hit or a
+    /// miss.
+    ///
+    /// The cache is not locked while `f` runs, so other keys stay usable.
+    /// Concurrent calls for the same key wait for one computation and get
+    /// its value as a hit.
+    pub async fn get_or_insert_with<F>(&self, key: K, f: F) -> Lookup<V>
+    where
+        F: AsyncFnOnce() -> V,
+    {
+        if let Some(value) = self.get(&key).await {
+            return Lookup::Hit(value);
+        }
+        self.in_flight
+            .load(&key, || async {
+                // Another call may have inserted it since the check above
+                if let Some(value) = self.get(&key).await {
+                    return Lookup::Hit(value);
+                }
+                let value = f().await;
+                self.insert(key.clone(), value.clone()).await;
+                Lookup::Miss(value)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn async_lru_cache_works() {
+        let cache = AsyncLruCache::new(2);
+        cache.insert(1, "one").await;
+        cache.insert(2, "two").await;
+        assert_eq!(cache.get(&1).await, Some("one"));
+
+        cache.insert(3, "three").await;
+        assert!(!cache.contains_key(&2).await);
+        assert_eq!(cache.len().await, 2);
+        assert_eq!(cache.remove(&1).await, Some("one"));
+        cache.clear().await;
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_loads_of_a_key_run_once() {
+        let cache = Arc::new(AsyncLruCache::new(8));
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let loads = Arc::clone(&loads);
+                tokio::spawn(async move {
+                    cache
+                        .get_or_insert_with("key", async || {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            42
+                        })
+                        .await
+                })
+            })
+            .collect();
+        let mut misses = 0;
+        for task in tasks {
+            let lookup = task.await.unwrap();
+            assert_eq!(*lookup.value(), 42);
+            misses += usize::from(lookup.is_miss());
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(misses, 1);
+        assert!(cache.in_flight.calls.lock().unwrap().is_empty());
+        assert_eq!(cache.get_or_insert_with("key", async || 0).await, Lookup::Hit(42));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_cancelled_load_is_retried() {
+        let cache = AsyncLruCache::new(8);
+        let slow = cache.get_or_insert_with(1, async || {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            "slow"
+        });
+        assert!(tokio::time::timeout(Duration::from_secs(1), slow).await.is_err());
+        assert!(cache.in_flight.calls.lock().unwrap().is_empty());
+
+        assert_eq!(cache.get_or_insert_with(1, async || "fast").await, Lookup::Miss("fast"));
+        assert_eq!(cache.get(&1).await, Some("fast"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use crate::cache::Cache;
+use crate::policy::EvictionPolicy;
+
+/// The `insert/get/remove` surface shared by the fixed-capacity caches, so
+/// code can be written once and run against any eviction strategy.
+pub trait BoundedCache<K, V> {
+    /// Insert a key-value pair, evicting entries if the cache is full.
+    fn insert(&mut self, key: K, value: V);
+
+    /// Get a value associated with a key, counting it as a use.
+    fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Remove a key-value pair from the cache.
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Returns `true` if the key is cached, without counting it as a use.
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Number of entries in the cache.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the cache holds no entries.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Maximum number of entries, or total weight for weighted caches.
+    fn capacity(&self) -> usize;
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy> BoundedCache<K, V> for Cache<K, V, P> {
+    fn insert(&mut self, key: K, value: V) {
+        Cache::insert(self, key, value)
+    }
+
+    fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Cache::get(self, key)
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Cache::remove(self, key)
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Cache::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        Cache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        Cache::capacity(self)
+    }
+}
+
+/// Helpers for tests that compare eviction strategies.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::BoundedCache;
+
+    /// Look `key` up, caching it on a miss. Returns whether it was a hit.
+    pub(crate) fn touch<C: BoundedCache<u32, u32>>(cache: &mut C, key: u32) -> bool {
+        if cache.get(&key).is_some() {
+            return true;
+        }
+        cache.insert(key, key);
+        false
+    }
+}
@@ -0,0 +1,604 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use crate::listener::{RemovalCause, RemovalListener, RemovalQueue, Removals};
+use crate::lookup::Lookup;
+use crate::policy::{EvictionPolicy, Lru};
+use crate::slab::Slab;
+use crate::weigher::{UnitWeigher, Weigher};
+
+/// Bounded cache whose eviction order is chosen by an [`EvictionPolicy`].
+///
+/// Entries live in a slab and the policy tracks them by slot index, so
+/// lookups and inserts cost one hash plus the policy's bookkeeping, and
+/// dropping the cache frees every node.
+///
+/// The capacity bounds the total weight of the entries. By default every
+/// entry weighs 1, so it is simply the maximum number of entries; use
+/// [`Cache::with_weigher`] to bound the cache by, say, bytes instead.
+pub struct Cache<K, V, P = Lru> {
+    map: HashMap<K, usize>,
+    entries: Slab<Node<K, V>>,
+    policy: P,
+    capacity: usize,
+    weigher: Box<dyn Weigher<K, V> + Send + Sync>,
+    total_weight: usize,
+    removals: RemovalQueue<K, V>,
+}
+
+/// Entry stored in the slab.
+struct Node<K, V> {
+    key: K,
+    value: V,
+    weight: usize,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy + Default> Cache<K, V, P> {
+    /// Create a new cache with a given capacity.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, P::default())
+    }
+
+    /// Create a new cache whose entries may weigh at most `max_weight` in total.
+    pub fn with_weigher<W>(max_weight: usize, weigher: W) -> Self
+    where
+        W: Weigher<K, V> + Send + Sync + 'static,
+    {
+        Self::with_policy_and_weigher(max_weight, P::default(), weigher)
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy> Cache<K, V, P> {
+    /// Create a new cache with a given capacity and a configured policy.
+    pub fn with_policy(capacity: usize, policy: P) -> Self {
+        Self::with_policy_and_weigher(capacity, policy, UnitWeigher)
+    }
+
+    /// Create a new cache with a configured policy whose entries may weigh
+    /// at most `max_weight` in total.
+    pub fn with_policy_and_weigher<W>(max_weight: usize, policy: P, weigher: W) -> Self
+    where
+        W: Weigher<K, V> + Send + Sync + 'static,
+    {
+        Self {
+            map: HashMap::new(),
+            entries: Slab::new(),
+            policy,
+            capacity: max_weight,
+            weigher: Box::new(weigher),
+            total_weight: 0,
+            removals: RemovalQueue::new(),
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache, e.g. to write evicted values back to storage.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.removals.set_listener(Arc::new(listener));
+        self
+    }
+
+    pub(crate) fn set_removal_listener(&mut self, listener: RemovalListener<K, V>) {
+        self.removals.set_listener(listener);
+    }
+
+    /// Sum of the weights of all entries currently in the cache.
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    /// Maximum total weight; the maximum number of entries unless a weigher is set.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns `true` if the key is cached, without marking it as used.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Get a reference to a value without marking it as used.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        Some(&self.entries.get(idx).value)
+    }
+
+    /// Get a mutable reference to a value without marking it as used.
+    ///
+    /// The entry keeps the weight computed when it was inserted.
+    pub fn peek_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        Some(&mut self.entries.get_mut(idx).value)
+    }
+
+    /// The entry the policy would evict next.
+    pub fn peek_victim(&self) -> Option<(&K, &V)> {
+        let node = self.entries.get(self.policy.victim()?);
+        Some((&node.key, &node.value))
+    }
+
+    /// Remove and return the entry the policy would evict next.
+    pub fn pop_victim(&mut self) -> Option<(K, V)> {
+        let idx = self.policy.victim()?;
+        let node = self.unlink(idx);
+        self.removals.record_cloned(node.key.clone(), &node.value, RemovalCause::Explicit);
+        self.removals.notify();
+        Some((node.key, node.value))
+    }
+
+    /// Change the capacity, keeping the cached entries where possible.
+    ///
+    /// When shrinking, entries are evicted in policy order until the rest
+    /// fit. They are reported to the removal listener as
+    /// `RemovalCause::Evicted` and also returned, in eviction order.
+    pub fn resize(&mut self, new_capacity: usize) -> Vec<(K, V)> {
+        let evicted = self.resize_deferred(new_capacity);
+        self.removals.notify();
+        evicted
+    }
+
+    /// Remove every entry, reporting each as `RemovalCause::Cleared`.
+    pub fn clear(&mut self) {
+        while let Some(idx) = self.policy.victim() {
+            let node = self.unlink(idx);
+            self.removals.record(node.key, node.value, RemovalCause::Cleared);
+        }
+        self.removals.notify();
+    }
+
+    /// Iterate over the entries in the policy's order, without marking them
+    /// as used.
+    pub fn iter(&self) -> Iter<'_, K, V, P> {
+        Iter {
+            entries: &self.entries,
+            policy: &self.policy,
+            front: self.policy.first(),
+            back: self.policy.last(),
+            len: self.map.len(),
+        }
+    }
+
+    /// Iterate over the keys in the policy's order.
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> + ExactSizeIterator {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// Iterate over the values in the policy's order.
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> + ExactSizeIterator {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Insert a key-value pair into the cache.
+    ///
+    /// Entries are evicted in policy order until the new entry fits. An
+    /// entry heavier than the whole capacity is rejected: it is not stored,
+    /// and any older value under the same key is removed.
+    pub fn insert(&mut self, key: K, value: V) {
+        let _ = self.insert_deferred(key, value);
+        self.removals.notify();
+    }
+
+    /// Get the entry for a key, for in-place lookup and insertion.
+    ///
+    /// An existing entry is marked as used.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, P> {
+        match self.map.get(&key) {
+            Some(&idx) => {
+                self.policy.on_access(idx);
+                Entry::Occupied(OccupiedEntry { cache: self, idx })
+            }
+            None => Entry::Vacant(VacantEntry { cache: self, key }),
+        }
+    }
+
+    /// Get the value for `key`, computing and inserting it with `f` if it is
+    /// not cached. The result tells whether the value was a hit or a miss.
+    ///
+    /// A computed value heavier than the whole capacity is returned but not
+    /// stored.
+    pub fn get_or_insert_with<F>(&mut self, key: K, f: F) -> Lookup<V>
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(value) = self.get(&key) {
+            return Lookup::Hit(value);
+        }
+        let value = f();
+        self.insert(key, value.clone());
+        Lookup::Miss(value)
+    }
+
+    /// Get a value associated with a key.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        self.policy.on_access(idx);
+        Some(self.entries.get(idx).value.clone())
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let value = self.remove_deferred(key);
+        self.removals.notify();
+        value
+    }
+
+    /// `insert` without calling the removal listener; the removals are kept
+    /// for `take_removals`. Returns the slot of the stored entry, or hands
+    /// the value back if it was rejected for being too heavy.
+    pub(crate) fn insert_deferred(&mut self, key: K, value: V) -> Result<usize, V> {
+        let weight = self.weigher.weigh(&key, &value);
+        if weight > self.capacity {
+            if let Some(idx) = self.map.get(&key).copied() {
+                let node = self.unlink(idx);
+                self.removals.record(node.key, node.value, RemovalCause::Replaced);
+            }
+            return Err(value);
+        }
+
+        if let Some(&idx) = self.map.get(&key) {
+            // Key exists, update value and count it as a use
+            self.policy.on_access(idx);
+            let old_value = self.replace_deferred(idx, value, weight);
+            self.removals.record(key, old_value, RemovalCause::Replaced);
+            self.evict_around(idx);
+            return Ok(idx);
+        }
+
+        // Evict items until the new one fits
+        self.evict_to(self.capacity - weight, None);
+
+        let idx = self.entries.insert(Node {
+            key: key.clone(),
+            value,
+            weight,
+        });
+        self.policy.on_insert(idx);
+        self.map.insert(key, idx);
+        self.total_weight += weight;
+        Ok(idx)
+    }
+
+    /// Swap in the value of the entry at `idx`, which weighs `weight`,
+    /// returning the old value. The cache may be over capacity afterwards.
+    fn replace_deferred(&mut self, idx: usize, value: V, weight: usize) -> V {
+        let node = self.entries.get_mut(idx);
+        self.total_weight = self.total_weight - node.weight + weight;
+        node.weight = weight;
+        std::mem::replace(&mut node.value, value)
+    }
+
+    /// Evict entries other than the one at `idx` until the cache fits its
+    /// capacity again.
+    fn evict_around(&mut self, idx: usize) {
+        self.evict_to(self.capacity, Some(idx));
+    }
+
+    /// `remove` without calling the removal listener.
+    pub(crate) fn remove_deferred<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        let node = self.unlink(idx);
+        self.removals.record_cloned(node.key, &node.value, RemovalCause::Explicit);
+        Some(node.value)
+    }
+
+    /// `resize` without calling the removal listener.
+    pub(crate) fn resize_deferred(&mut self, new_capacity: usize) -> Vec<(K, V)> {
+        self.capacity = new_capacity;
+        self.removals.collect_evictions();
+        self.evict_to(new_capacity, None);
+        self.removals.take_evictions()
+    }
+
+    /// Removals recorded since the last call, for callers that must release
+    /// a lock before running the listener.
+    pub(crate) fn take_removals(&mut self) -> Removals<K, V> {
+        self.removals.take()
+    }
+
+    /// Take the node at `idx` out of the map, the policy and the slab.
+    fn unlink(&mut self, idx: usize) -> Node<K, V> {
+        self.policy.on_remove(idx);
+        let node = self.entries.remove(idx);
+        self.map.remove(&node.key);
+        self.total_weight -= node.weight;
+        node
+    }
+
+    /// Evict items until the total weight is at most `max_weight`, never
+    /// picking the entry at `keep`.
+    fn evict_to(&mut self, max_weight: usize, keep: Option<usize>) {
+        while self.total_weight > max_weight {
+            let victim = match keep {
+                Some(keep) => self.policy.victim_excluding(keep),
+                None => self.policy.victim(),
+            };
+            let Some(idx) = victim else {
+                break;
+            };
+            let node = self.unlink(idx);
+            self.removals.record(node.key, node.value, RemovalCause::Evicted);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Cache<K, V, Lru> {
+    /// The least recently used entry, which is the next one to be evicted.
+    pub fn peek_lru(&self) -> Option<(&K, &V)> {
+        self.peek_victim()
+    }
+
+    /// Remove and return the least recently used entry.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        self.pop_victim()
+    }
+}
+
+/// A view into a single entry of a [`Cache`], which may be vacant or occupied.
+pub enum Entry<'a, K, V, P = Lru> {
+    Occupied(OccupiedEntry<'a, K, V, P>),
+    Vacant(VacantEntry<'a, K, V, P>),
+}
+
+/// An entry that is already cached.
+pub struct OccupiedEntry<'a, K, V, P = Lru> {
+    cache: &'a mut Cache<K, V, P>,
+    idx: usize,
+}
+
+/// An entry that is not cached yet.
+pub struct VacantEntry<'a, K, V, P = Lru> {
+    cache: &'a mut Cache<K, V, P>,
+    key: K,
+}
+
+impl<'a, K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy> Entry<'a, K, V, P> {
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Insert `default` if the entry is vacant, and return the cached value.
+    ///
+    /// A value heavier than the whole capacity of the cache is handed back
+    /// as an error instead of being stored.
+    pub fn or_insert(self, default: V) -> Result<&'a mut V, V> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Insert the result of `f` if the entry is vacant, and return the cached value.
+    ///
+    /// A value heavier than the whole capacity of the cache is handed back
+    /// as an error instead of being stored.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> Result<&'a mut V, V> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => entry.insert(f()),
+        }
+    }
+
+    /// Like `or_insert_with`, but `f` may fail, in which case nothing is
+    /// inserted and its error is returned. The inner result hands back a
+    /// value too heavy to store, as `or_insert_with` does.
+    pub fn or_try_insert_with<F, E>(self, f: F) -> Result<Result<&'a mut V, V>, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        match self {
+            Entry::Occupied(entry) => Ok(Ok(entry.into_mut())),
+            Entry::Vacant(entry) => Ok(entry.insert(f()?)),
+        }
+    }
+
+    /// Modify the cached value in place if the entry is occupied.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy> OccupiedEntry<'a, K, V, P> {
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.cache.entries.get(self.idx).key
+    }
+
+    /// A reference to the cached value.
+    pub fn get(&self) -> &V {
+        &self.cache.entries.get(self.idx).value
+    }
+
+    /// A mutable reference to the cached value. The entry keeps the weight
+    /// computed when it was inserted.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.cache.entries.get_mut(self.idx).value
+    }
+
+    /// Turn the entry into a mutable reference bound to the cache's lifetime.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.cache.entries.get_mut(self.idx).value
+    }
+
+    /// Replace the cached value, returning the old one. Other entries are
+    /// evicted in policy order if the new value is heavier.
+    ///
+    /// A value heavier than the whole capacity of the cache is handed back
+    /// as an error, leaving the entry unchanged.
+    pub fn insert(&mut self, value: V) -> Result<V, V> {
+        let cache = &mut *self.cache;
+        let weight = cache.weigher.weigh(&cache.entries.get(self.idx).key, &value);
+        if weight > cache.capacity {
+            return Err(value);
+        }
+        let old_value = cache.replace_deferred(self.idx, value, weight);
+        let key = cache.entries.get(self.idx).key.clone();
+        cache.removals.record_cloned(key, &old_value, RemovalCause::Replaced);
+        cache.evict_around(self.idx);
+        cache.removals.notify();
+        Ok(old_value)
+    }
+
+    /// Remove the entry from the cache, returning its value.
+    pub fn remove(self) -> V {
+        let key = self.key().clone();
+        self.cache
+            .remove(&key)
+            .expect("occupied entry is in the cache")
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy> VacantEntry<'a, K, V, P> {
+    /// The key this entry would be inserted under.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Insert the value, evicting entries in policy order to make room.
+    ///
+    /// A value heavier than the whole capacity of the cache is handed back
+    /// as an error, leaving the cache unchanged.
+    pub fn insert(self, value: V) -> Result<&'a mut V, V> {
+        let idx = self.cache.insert_deferred(self.key, value)?;
+        self.cache.removals.notify();
+        Ok(&mut self.cache.entries.get_mut(idx).value)
+    }
+}
+
+/// Iterator over the entries of a [`Cache`], in the policy's order.
+pub struct Iter<'a, K, V, P = Lru> {
+    entries: &'a Slab<Node<K, V>>,
+    policy: &'a P,
+    front: Option<usize>,
+    back: Option<usize>,
+    len: usize,
+}
+
+impl<'a, K, V, P: EvictionPolicy> Iterator for Iter<'a, K, V, P> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let idx = self.front?;
+        self.front = self.policy.next(idx);
+        self.len -= 1;
+        let node = self.entries.get(idx);
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V, P: EvictionPolicy> DoubleEndedIterator for Iter<'_, K, V, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let idx = self.back?;
+        self.back = self.policy.prev(idx);
+        self.len -= 1;
+        let node = self.entries.get(idx);
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<K, V, P: EvictionPolicy> ExactSizeIterator for Iter<'_, K, V, P> {}
+
+impl<K, V, P: EvictionPolicy> FusedIterator for Iter<'_, K, V, P> {}
+
+impl<'a, K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy> IntoIterator for &'a Cache<K, V, P> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, P>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V, P> fmt::Debug for Cache<K, V, P>
+where
+    K: Hash + Eq + Clone + fmt::Debug,
+    V: Clone + fmt::Debug,
+    P: EvictionPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, P: EvictionPolicy> Extend<(K, V)> for Cache<K, V, P> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Collects into a cache just large enough for every distinct key. A key
+/// that appears more than once keeps its last value.
+impl<K, V, P> FromIterator<(K, V)> for Cache<K, V, P>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    P: EvictionPolicy + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let items: Vec<(K, V)> = iter.into_iter().collect();
+        let distinct = items.iter().map(|(key, _)| key).collect::<HashSet<_>>().len();
+        let mut cache = Self::new(distinct);
+        cache.extend(items);
+        cache
+    }
+}
+
@@ -0,0 +1,104 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of the current time for caches with time-based expiry.
+pub trait Clock: Send + Sync {
+    /// The current instant. Must never go backwards.
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time, for deadlines given as a `SystemTime`.
+    /// Unlike `now`, it may jump when the system clock is adjusted.
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// The real monotonic clock, [`Instant::now`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Tokio's clock, [`tokio::time::Instant::now`]. Unlike [`SystemClock`] it
+/// stands still while a test has paused time, and moves with
+/// `tokio::time::advance`.
+#[cfg(feature = "async")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "async")]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+}
+
+/// A clock that only moves when told to, for testing expiry without
+/// sleeping. Clones share the same time, so a test can keep one and hand
+/// another to the cache.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    now: Arc<Mutex<(Instant, SystemTime)>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    /// A clock stopped at the current instant and wall-clock time.
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new((Instant::now(), SystemTime::now()))),
+        }
+    }
+
+    /// Move the time forward by `by`, on both the monotonic and wall clocks.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += by;
+        now.1 += by;
+    }
+
+    /// Set the wall-clock time without moving the monotonic one, as when
+    /// the system clock is adjusted.
+    pub fn set_system_time(&self, time: SystemTime) {
+        self.now.lock().unwrap().1 = time;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_moves_only_when_advanced() {
+        let clock = MockClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        shared.advance(Duration::from_secs(90));
+        assert_eq!(clock.now(), start + Duration::from_secs(90));
+
+        let wall = clock.system_time();
+        clock.set_system_time(wall - Duration::from_secs(3600));
+        assert_eq!(clock.now(), start + Duration::from_secs(90));
+        assert_eq!(shared.system_time(), wall - Duration::from_secs(3600));
+    }
+}
@@ -0,0 +1,376 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::bounded_cache::BoundedCache;
+use crate::listener::{self, RemovalCause, RemovalListener, Removals};
+
+/// CLOCK (second chance) cache for read-mostly workloads.
+///
+/// Entries sit in a fixed ring of slots, each with a reference bit. A hit
+/// only sets the bit, which is atomic, so `get` takes a shared lock and
+/// readers never wait on each other. To make room, a clock hand sweeps the
+/// ring, clearing set bits and evicting the first entry whose bit was
+/// already clear, i.e. one not read since the hand last passed it.
+pub struct ClockCache<K, V> {
+    inner: RwLock<Ring<K, V>>,
+    listener: Option<RemovalListener<K, V>>,
+}
+
+struct Ring<K, V> {
+    map: HashMap<K, usize>,
+    slots: Vec<Option<Node<K, V>>>,
+    referenced: Box<[AtomicBool]>,
+    /// Empty slots, used before the hand has to evict anything.
+    free: Vec<usize>,
+    hand: usize,
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Ring<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            slots: (0..capacity).map(|_| None).collect(),
+            referenced: (0..capacity).map(|_| AtomicBool::new(false)).collect(),
+            free: (0..capacity).rev().collect(),
+            hand: 0,
+        }
+    }
+
+    /// Advance the hand to the first entry not referenced since its last
+    /// pass and empty its slot, passing over empty slots. The ring must hold
+    /// at least one entry.
+    fn evict(&mut self) -> Node<K, V> {
+        loop {
+            let idx = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            if self.slots[idx].is_some() && !self.referenced[idx].swap(false, Ordering::Relaxed) {
+                return self.take(idx);
+            }
+        }
+    }
+
+    fn take(&mut self, idx: usize) -> Node<K, V> {
+        let node = self.slots[idx].take().expect("mapped slot is occupied");
+        self.map.remove(&node.key);
+        self.free.push(idx);
+        node
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ClockCache<K, V> {
+    /// Create a new cache holding at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: RwLock::new(Ring::new(capacity)),
+            listener: None,
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache. It runs after the lock is released, so it may use
+    /// the cache itself.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.inner.read().unwrap().slots.len()
+    }
+
+    /// Number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the key is cached, without setting its reference bit.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.read().unwrap().map.contains_key(key)
+    }
+
+    /// Get a value associated with a key.
+    ///
+    /// Only takes a shared lock, so concurrent readers do not block each other.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let ring = self.inner.read().unwrap();
+        let idx = *ring.map.get(key)?;
+        ring.referenced[idx].store(true, Ordering::Relaxed);
+        ring.slots[idx].as_ref().map(|node| node.value.clone())
+    }
+
+    /// Insert a key-value pair into the cache, evicting an entry if it is full.
+    pub fn insert(&self, key: K, value: V) {
+        let mut removals = Removals::new();
+        {
+            let mut ring = self.inner.write().unwrap();
+            if let Some(&idx) = ring.map.get(&key) {
+                let node = ring.slots[idx].as_mut().expect("mapped slot is occupied");
+                let old_value = std::mem::replace(&mut node.value, value);
+                ring.referenced[idx].store(true, Ordering::Relaxed);
+                removals.push((key, old_value, RemovalCause::Replaced));
+            } else if !ring.slots.is_empty() {
+                if ring.free.is_empty() {
+                    let node = ring.evict();
+                    removals.push((node.key, node.value, RemovalCause::Evicted));
+                }
+                let idx = ring.free.pop().expect("eviction freed a slot");
+                ring.referenced[idx].store(false, Ordering::Relaxed);
+                ring.slots[idx] = Some(Node {
+                    key: key.clone(),
+                    value,
+                });
+                ring.map.insert(key, idx);
+            }
+        }
+        listener::notify(self.listener.as_ref(), removals);
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = {
+            let mut ring = self.inner.write().unwrap();
+            let idx = *ring.map.get(key)?;
+            ring.take(idx)
+        };
+        if let Some(listener) = &self.listener {
+            listener(node.key, node.value.clone(), RemovalCause::Explicit);
+        }
+        Some(node.value)
+    }
+
+    /// Remove every entry, reporting each as `RemovalCause::Cleared`.
+    pub fn clear(&self) {
+        let removals: Removals<K, V> = {
+            let mut ring = self.inner.write().unwrap();
+            let slots: Vec<usize> = ring.map.values().copied().collect();
+            slots
+                .into_iter()
+                .map(|idx| {
+                    let node = ring.take(idx);
+                    (node.key, node.value, RemovalCause::Cleared)
+                })
+                .collect()
+        };
+        listener::notify(self.listener.as_ref(), removals);
+    }
+
+    /// Change the capacity, rebuilding the ring. When shrinking, the hand
+    /// evicts entries until the rest fit; they are reported to the removal
+    /// listener as `RemovalCause::Evicted` and also returned.
+    pub fn resize(&self, new_capacity: usize) -> Vec<(K, V)> {
+        let evicted: Vec<(K, V)> = {
+            let mut ring = self.inner.write().unwrap();
+            let mut evicted = Vec::new();
+            while ring.map.len() > new_capacity {
+                let node = ring.evict();
+                evicted.push((node.key, node.value));
+            }
+            let old = std::mem::replace(&mut *ring, Ring::new(new_capacity));
+            let live = old
+                .slots
+                .into_iter()
+                .zip(old.referenced.iter())
+                .filter_map(|(node, bit)| Some((node?, bit.load(Ordering::Relaxed))));
+            for (idx, (node, referenced)) in live.enumerate() {
+                ring.referenced[idx].store(referenced, Ordering::Relaxed);
+                ring.map.insert(node.key.clone(), idx);
+                ring.slots[idx] = Some(node);
+            }
+            ring.free = (ring.map.len()..new_capacity).rev().collect();
+            evicted
+        };
+        if let Some(listener) = &self.listener {
+            for (key, value) in &evicted {
+                listener(key.clone(), value.clone(), RemovalCause::Evicted);
+            }
+        }
+        evicted
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> BoundedCache<K, V> for ClockCache<K, V> {
+    fn insert(&mut self, key: K, value: V) {
+        ClockCache::insert(self, key, value)
+    }
+
+    fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        ClockCache::get(self, key)
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        ClockCache::remove(self, key)
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        ClockCache::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        ClockCache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        ClockCache::capacity(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn clock_cache_works() {
+        let cache = ClockCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.remove("a"), Some(1));
+        assert_eq!(cache.get("a"), None);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_stores_nothing() {
+        let cache = ClockCache::new(0);
+        cache.insert(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn referenced_entries_get_a_second_chance() {
+        let cache = ClockCache::new(3);
+        for key in 1..=3 {
+            cache.insert(key, key);
+        }
+        cache.get(&1);
+        // The hand skips 1, clearing its bit, and evicts 2
+        cache.insert(4, 4);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        // 1 was not read again, so it goes when the hand comes back around
+        cache.insert(5, 5);
+        cache.insert(6, 6);
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn resize_keeps_referenced_entries() {
+        let cache = ClockCache::new(4);
+        for key in 0..4 {
+            cache.insert(key, key);
+        }
+        cache.get(&3);
+        let mut evicted = cache.resize(1);
+        evicted.sort_unstable();
+        assert_eq!(evicted, vec![(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(cache.capacity(), 1);
+        assert_eq!(cache.get(&3), Some(3));
+
+        cache.resize(3);
+        cache.insert(4, 4);
+        cache.insert(5, 5);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn resize_after_remove_passes_over_empty_slots() {
+        let cache = ClockCache::new(4);
+        for key in 0..4 {
+            cache.insert(key, key);
+        }
+        // The hand starts on the slot freed here
+        cache.remove(&0);
+        assert_eq!(cache.resize(2), vec![(1, 1)]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&2), Some(2));
+        assert_eq!(cache.get(&3), Some(3));
+    }
+
+    #[test]
+    fn removal_listener_reports_causes() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let cache = ClockCache::new(1)
+            .with_removal_listener(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+
+        cache.insert(1, "one");
+        cache.insert(1, "uno");
+        cache.insert(2, "two");
+        cache.remove(&2);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (1, "one", RemovalCause::Replaced),
+                (1, "uno", RemovalCause::Evicted),
+                (2, "two", RemovalCause::Explicit),
+            ]
+        );
+    }
+
+    #[test]
+    fn concurrent_readers_share_the_cache() {
+        assert_send_sync::<ClockCache<String, Vec<u8>>>();
+
+        let cache = Arc::new(ClockCache::new(100));
+        for key in 0..100 {
+            cache.insert(key, key * 2);
+        }
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || (0..100).filter(|key| cache.get(key) == Some(key * 2)).count())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 100);
+        }
+    }
+}
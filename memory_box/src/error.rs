@@ -0,0 +1,22 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Why an [`ExpireCache`](crate::ExpireCache) could not store a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TtlError {
+    /// The TTL reaches past the latest instant the platform can represent.
+    TooLong(Duration),
+    /// No TTL was given and the cache has no default TTL.
+    NoDefault,
+}
+
+impl fmt::Display for TtlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TtlError::TooLong(ttl) => write!(f, "TTL of {:?} is too long to represent", ttl),
+            TtlError::NoDefault => write!(f, "no TTL given and the cache has no default TTL"),
+        }
+    }
+}
+
+impl std::error::Error for TtlError {}
@@ -1,209 +1,1467 @@
-// use std::collections::HashMap;
-// use std::time::{Duration, Instant};
-// use tokio::sync::Mutex;
-// use tokio::time::sleep;
-// use std::sync::Arc;
-
-// #[derive(Clone)]
-// struct ExpiringValue<V> {
-//     value: V,
-//     expiry: Instant,
-// }
-
-// pub struct ExpireCache<K, V> {
-//     map: Arc<Mutex<HashMap<K, ExpiringValue<V>>>>,
-// }
-
-// impl<K: std::hash::Hash + Eq + Clone + Send + 'static, V: Clone + Send + 'static> ExpireCache<K, V> {
-//     pub fn new() -> Self {
-//         let cache = Self {
-//             map: Arc::new(Mutex::new(HashMap::new())),
-//         };
-
-//         let map_clone = cache.map.clone();
-//         tokio::spawn(async move {
-//             loop {
-//                 sleep(Duration::new(10, 0)).await;
-//                 let mut map = map_clone.lock().await;
-//                 let now = Instant::now();
-//                 map.retain(|_, v| v.expiry > now);
-//             }
-//         });
-
-//         cache
-//     }
-
-//     pub async fn insert(&self, key: K, value: V, ttl: usize) {
-//         let expiry = Instant::now() + Duration::from_secs(ttl as u64);
-//         let expiring_value = ExpiringValue { value, expiry };
-
-//         let mut map = self.map.lock().await;
-//         map.insert(key, expiring_value);
-//     }
-
-//     pub async fn get(&self, key: &K) -> Option<V> {
-//         let mut map = self.map.lock().await;
-//         if let Some(expiring_value) = map.get(key) {
-//             if expiring_value.expiry > Instant::now() {
-//                 return Some(expiring_value.value.clone());
-//             } else {
-//                 map.remove(key);
-//             }
-//         }
-//         None
-//     }
-
-//     pub async fn remove(&self, key: &K) -> Option<V> {
-//         let mut map = self.map.lock().await;
-//         map.remove(key).map(|expiring_value| expiring_value.value)
-//     }
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use tokio::time::Duration;
-
-//     #[tokio::test]
-//     async fn test_insert_and_get() {
-//         let cache = ExpireCache::new();
-//         cache.insert("key1", "value1", Duration::new(5, 0)).await;
-
-//         sleep(Duration::new(3, 0)).await;
-//         assert_eq!(cache.get(&"key1").await, Some("value1"));
-
-//         sleep(Duration::new(3, 0)).await;
-//         assert_eq!(cache.get(&"key1").await, None);
-//     }
-
-//     #[tokio::test]
-//     async fn test_remove() {
-//         let cache = ExpireCache::new();
-//         cache.insert("key1", "value1", Duration::new(5, 0)).await;
-
-//         sleep(Duration::new(1, 0)).await;
-//         assert_eq!(cache.remove(&"key1").await, Some("value1"));
-//         assert_eq!(cache.get(&"key1").await, None);
-//     }
-
-//     #[tokio::test]
-//     async fn test_expiry() {
-//         let cache = ExpireCache::new();
-//         cache.insert("key1", "value1", Duration::new(2, 0)).await;
-
-//         sleep(Duration::new(3, 0)).await;
-//         assert_eq!(cache.get(&"key1").await, None);
-//     }
-
-//     #[tokio::test]
-//     async fn test_multiple_keys() {
-//         let cache = ExpireCache::new();
-//         cache.insert("key1", "value1", Duration::new(5, 0)).await;
-//         cache.insert("key2", "value2", Duration::new(3, 0)).await;
-
-//         sleep(Duration::new(2, 0)).await;
-//         assert_eq!(cache.get(&"key1").await, Some("value1"));
-//         assert_eq!(cache.get(&"key2").await, Some("value2"));
-
-//         sleep(Duration::new(2, 0)).await;
-//         assert_eq!(cache.get(&"key1").await, Some("value1"));
-//         assert_eq!(cache.get(&"key2").await, None);
-
-//         sleep(Duration::new(2, 0)).await;
-//         assert_eq!(cache.get(&"key1").await, None);
-//     }
-// }
-use std::collections::HashMap;
-use std::sync::RwLock;
-use std::time::{Duration as StdDuration, Instant};
-
-#[derive(Clone)]
-struct ExpiringValue<V> {
-    value: V,
-    expiry: Instant,
-}
-
-pub struct ExpireCache<K, V> {
-    map: RwLock<HashMap<K, ExpiringValue<V>>>,
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockWriteGuard, Weak};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::TtlError;
+use crate::expiry::Expiry;
+use crate::listener::{self, RemovalCause, RemovalListener, Removals};
+use crate::lookup::Lookup;
+use crate::reaper::{self, ReaperHandle};
+
+/// The moment a value stops being valid, on the monotonic or wall clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deadline {
+    /// A point on the monotonic clock.
+    Instant(Instant),
+    /// A point on the wall clock, such as a token's `exp` claim. It is
+    /// compared against the wall clock every time, so it holds even if the
+    /// system clock is adjusted after the value is inserted.
+    SystemTime(SystemTime),
+}
+
+impl From<Instant> for Deadline {
+    fn from(instant: Instant) -> Self {
+        Deadline::Instant(instant)
+    }
+}
+
+impl From<SystemTime> for Deadline {
+    fn from(time: SystemTime) -> Self {
+        Deadline::SystemTime(time)
+    }
+}
+
+impl Deadline {
+    /// The deadline as an instant, given the current time. `None` if it is
+    /// too far off to represent.
+    fn at(self, now: Now) -> Option<Instant> {
+        match self {
+            Deadline::Instant(instant) => Some(instant),
+            Deadline::SystemTime(time) => match time.duration_since(now.wall) {
+                Ok(left) => now.instant.checked_add(left),
+                Err(_) => Some(now.instant),
+            },
+        }
+    }
+
+    fn checked_add(self, by: Duration) -> Option<Self> {
+        match self {
+            Deadline::Instant(instant) => instant.checked_add(by).map(Deadline::Instant),
+            Deadline::SystemTime(time) => time.checked_add(by).map(Deadline::SystemTime),
+        }
+    }
+}
+
+/// A reading of both clocks.
+#[derive(Clone, Copy)]
+pub(crate) struct Now {
+    pub(crate) instant: Instant,
+    pub(crate) wall: SystemTime,
+}
+
+impl Now {
+    pub(crate) fn of<C: Clock>(clock: &C) -> Self {
+        Self {
+            instant: clock.now(),
+            wall: clock.system_time(),
+        }
+    }
+}
+
+/// A live entry of an [`ExpireCache`], as saved by
+/// [`persist`](ExpireCache::persist). Its expiry is on the wall clock,
+/// which unlike an instant stays meaningful across restarts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PersistedEntry<K, V> {
+    pub key: K,
+    pub value: V,
+    pub expires_at: SystemTime,
+}
+
+pub(crate) struct ExpiringValue<V> {
+    pub(crate) value: V,
+    /// The TTL the entry was inserted with, restarted by `touch`.
+    ttl: Duration,
+    /// End of the entry's TTL. Reads do not move it; `touch` and
+    /// `extend_ttl` do.
+    deadline: Deadline,
+    /// When the entry was inserted or last read, in nanoseconds since the
+    /// cache's epoch. Atomic so reads can refresh it under a shared lock.
+    last_used: AtomicU64,
+    /// The expiry the entry is filed under in the index. Reads under a
+    /// time-to-idle move the real expiry later without refiling the entry;
+    /// cleanup refiles it when it finds the entry still live.
+    indexed: Instant,
+    /// Tie-breaker for entries filed under the same instant; with `indexed`
+    /// it is the entry's key in the expiry index.
+    seq: u64,
+}
+
+/// Entries plus an index of them ordered by expiry, so cleanup only visits
+/// the entries that are due.
+pub(crate) struct Entries<K, V> {
+    pub(crate) map: HashMap<K, ExpiringValue<V>>,
+    pub(crate) expiries: BTreeMap<(Instant, u64), K>,
+    /// Entries with a wall-clock deadline, ordered by it. Setting the wall
+    /// clock forward can expire them before their filed instant, so cleanup
+    /// sweeps this index too.
+    wall_deadlines: BTreeMap<(SystemTime, u64), K>,
+    next_seq: u64,
+    /// Reference point for `ExpiringValue::last_used`.
+    epoch: Instant,
+    pub(crate) time_to_idle: Option<Duration>,
+}
+
+impl<K: Hash + Eq + Clone, V> Entries<K, V> {
+    pub(crate) fn new(epoch: Instant) -> Self {
+        Self {
+            map: HashMap::new(),
+            expiries: BTreeMap::new(),
+            wall_deadlines: BTreeMap::new(),
+            next_seq: 0,
+            epoch,
+            time_to_idle: None,
+        }
+    }
+
+    /// When `entry` expires, seen from `now`: at its deadline, or earlier
+    /// if it goes unread for the time-to-idle.
+    pub(crate) fn expiry(&self, entry: &ExpiringValue<V>, now: Now) -> Instant {
+        // Only a wall clock set back by centuries puts a deadline that was
+        // representable on insertion out of reach
+        let deadline = entry.deadline.at(now).unwrap_or(entry.indexed);
+        let Some(idle) = self.time_to_idle else {
+            return deadline;
+        };
+        let last_used = self.epoch + Duration::from_nanos(entry.last_used.load(Ordering::Relaxed));
+        match last_used.checked_add(idle) {
+            Some(idle_expiry) => idle_expiry.min(deadline),
+            None => deadline,
+        }
+    }
+
+    /// Record a read of `entry` at `now`, pushing back its idle expiry.
+    pub(crate) fn touch(&self, entry: &ExpiringValue<V>, now: Now) {
+        if self.time_to_idle.is_some() {
+            entry.last_used.fetch_max(self.since_epoch(now.instant), Ordering::Relaxed);
+        }
+    }
+
+    fn since_epoch(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_nanos() as u64
+    }
+
+    /// The value for `key` if it has not expired by `now`.
+    pub(crate) fn live<Q>(&self, key: &Q, now: Now) -> Option<&ExpiringValue<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).filter(|entry| self.expiry(entry, now) > now.instant)
+    }
+
+    /// Store a value inserted at `now` with a `ttl` ending at `deadline`,
+    /// returning the one it replaces.
+    pub(crate) fn insert(&mut self, key: K, value: V, ttl: Duration, deadline: Deadline, now: Now) -> Option<ExpiringValue<V>> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mut entry = ExpiringValue {
+            value,
+            ttl,
+            deadline,
+            last_used: AtomicU64::new(self.since_epoch(now.instant)),
+            indexed: now.instant,
+            seq,
+        };
+        entry.indexed = self.expiry(&entry, now);
+        self.expiries.insert((entry.indexed, seq), key.clone());
+        if let Deadline::SystemTime(time) = deadline {
+            self.wall_deadlines.insert((time, seq), key.clone());
+        }
+        let old = self.map.insert(key, entry)?;
+        self.unfile(&old);
+        Some(old)
+    }
+
+    /// Drop `entry` from the indexes.
+    fn unfile(&mut self, entry: &ExpiringValue<V>) {
+        self.expiries.remove(&(entry.indexed, entry.seq));
+        if let Deadline::SystemTime(time) = entry.deadline {
+            self.wall_deadlines.remove(&(time, entry.seq));
+        }
+    }
+
+    /// Take out every entry, emptying the indexes.
+    pub(crate) fn clear(&mut self) -> Vec<(K, ExpiringValue<V>)> {
+        self.expiries.clear();
+        self.wall_deadlines.clear();
+        self.map.drain().collect()
+    }
+
+    pub(crate) fn remove<Q>(&mut self, key: &Q) -> Option<(K, ExpiringValue<V>)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, entry) = self.map.remove_entry(key)?;
+        self.unfile(&entry);
+        Some((key, entry))
+    }
+
+    /// Give the live entry for `key` a new TTL, starting at `now`.
+    fn restart<Q>(&mut self, key: &Q, ttl: Duration, now: Now) -> Result<(), TtlError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let deadline = deadline_after(now.instant, ttl)?;
+        self.set_deadline(key, deadline, now);
+        self.map.get_mut(key).expect("entry is present").ttl = ttl;
+        Ok(())
+    }
+
+    /// Move the deadline of the entry for `key` and refile it. The entry
+    /// must be present.
+    fn set_deadline<Q>(&mut self, key: &Q, deadline: Deadline, now: Now)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let entry = self.map.get_mut(key).expect("entry is present");
+        let old_deadline = std::mem::replace(&mut entry.deadline, deadline);
+        let filed = (entry.indexed, entry.seq);
+        let indexed = self.expiry(&self.map[key], now);
+        let owned_key = self.expiries.remove(&filed).expect("entry is indexed");
+        if let Deadline::SystemTime(time) = old_deadline {
+            self.wall_deadlines.remove(&(time, filed.1));
+        }
+        if let Deadline::SystemTime(time) = deadline {
+            self.wall_deadlines.insert((time, filed.1), owned_key.clone());
+        }
+        self.expiries.insert((indexed, filed.1), owned_key);
+        self.map.get_mut(key).expect("entry is present").indexed = indexed;
+    }
+
+    /// Remove every entry that has expired by `now`, soonest first, then
+    /// those whose wall-clock deadline has passed. Entries kept alive by
+    /// reads since they were filed are refiled instead.
+    pub(crate) fn remove_expired(&mut self, now: Now) -> Removals<K, V> {
+        let mut removals = Removals::new();
+        while let Some(filed) = self.expiries.first_entry() {
+            if filed.key().0 > now.instant {
+                break;
+            }
+            let ((_, seq), key) = filed.remove_entry();
+            let Some(entry) = self.map.get(&key) else {
+                continue;
+            };
+            let expiry = self.expiry(entry, now);
+            if expiry > now.instant {
+                self.expiries.insert((expiry, seq), key.clone());
+                self.map.get_mut(&key).expect("entry was just found").indexed = expiry;
+            } else if let Some((key, old)) = self.remove(&key) {
+                removals.push((key, old.value, RemovalCause::Expired));
+            }
+        }
+        while let Some(filed) = self.wall_deadlines.first_entry() {
+            if filed.key().0 > now.wall {
+                break;
+            }
+            let key = filed.remove();
+            if let Some((key, old)) = self.remove(&key) {
+                removals.push((key, old.value, RemovalCause::Expired));
+            }
+        }
+        removals
+    }
+}
+
+/// Cache whose entries expire a given time after they are inserted.
+///
+/// With a [time-to-idle](Self::with_time_to_idle), entries also expire
+/// once they go unread for that long, so they live while they are in use.
+///
+/// Time is read from the clock `C`, the system clock by default; tests can
+/// use a [`MockClock`](crate::MockClock) to expire entries without sleeping.
+pub struct ExpireCache<K, V, C = SystemClock> {
+    entries: RwLock<Entries<K, V>>,
+    default_ttl: Option<Duration>,
+    expiry: Option<Box<dyn Expiry<K, V>>>,
+    listener: Option<RemovalListener<K, V>>,
+    clock: C,
+}
+
+impl<K, V, C> Default for ExpireCache<K, V, C>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    C: Clock + Default,
+{
+    fn default() -> Self {
+        Self::with_clock(C::default())
+    }
 }
 
 impl<K, V> ExpireCache<K, V>
 where
-    K: std::hash::Hash + Eq + Clone,
+    K: Hash + Eq + Clone,
     V: Clone,
 {
     /// Creates a new `ExpireCache` instance.
     pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+
+    /// Creates a new `ExpireCache` whose entries live for `ttl` unless
+    /// inserted with a TTL of their own.
+    pub fn with_default_ttl(ttl: Duration) -> Self {
+        Self::with_default_ttl_and_clock(ttl, SystemClock)
+    }
+}
+
+impl<K, V, C> ExpireCache<K, V, C>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    C: Clock,
+{
+    /// Creates a new `ExpireCache` reading the time from `clock`.
+    pub fn with_clock(clock: C) -> Self {
         Self {
-            map: RwLock::new(HashMap::new()),
+            entries: RwLock::new(Entries::new(clock.now())),
+            default_ttl: None,
+            expiry: None,
+            listener: None,
+            clock,
         }
     }
 
-    /// Inserts a key-value pair with a time-to-live (TTL) in seconds.
-    ///
-    /// # Panics
-    /// Panics if the TTL exceeds 60 seconds.
-    pub fn insert(&self, key: K, value: V, ttl: usize) {
-        if ttl > 60 {
-            panic!("TTL cannot exceed 60 seconds");
+    /// Creates a new `ExpireCache` with a default TTL, reading the time
+    /// from `clock`.
+    pub fn with_default_ttl_and_clock(ttl: Duration, clock: C) -> Self {
+        Self {
+            default_ttl: Some(ttl),
+            ..Self::with_clock(clock)
         }
+    }
 
-        let expiry = Instant::now() + StdDuration::from_secs(ttl as u64);
-        let expiring_value = ExpiringValue { value, expiry };
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache, e.g. to count expirations. It runs after the
+    /// cache's lock is released, so it may use the cache itself.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Let `expiry` pick the TTL of each entry as it is inserted, replaced
+    /// or read, e.g. from the value itself. Where its hooks return `None`
+    /// the cache behaves as without it.
+    pub fn with_expiry<E>(mut self, expiry: E) -> Self
+    where
+        E: Expiry<K, V> + 'static,
+    {
+        self.expiry = Some(Box::new(expiry));
+        self
+    }
+
+    /// Also expire entries that go `idle` without being read, so each read
+    /// pushes an entry's expiry forward.
+    ///
+    /// The TTL an entry was inserted with still caps its total lifetime:
+    /// it expires after `idle` without reads or when its TTL runs out,
+    /// whichever comes first.
+    pub fn with_time_to_idle(mut self, idle: Duration) -> Self {
+        self.entries.get_mut().unwrap().time_to_idle = Some(idle);
+        self
+    }
+
+    /// The time-to-idle, if one was set.
+    pub fn time_to_idle(&self) -> Option<Duration> {
+        self.entries.read().unwrap().time_to_idle
+    }
+
+    /// The TTL used by `insert_default`, if one was set.
+    pub fn default_ttl(&self) -> Option<Duration> {
+        self.default_ttl
+    }
+
+    /// Inserts a key-value pair that lives for `ttl`, unless the cache's
+    /// [`Expiry`] picks another TTL. A zero TTL stores a value that has
+    /// already expired.
+    ///
+    /// Fails without changing the cache if the TTL is too long to represent.
+    pub fn insert(&self, key: K, value: V, ttl: Duration) -> Result<(), TtlError> {
+        self.insert_with_ttl(key, value, Some(ttl))
+    }
+
+    /// Inserts a key-value pair that lives for the TTL picked by the
+    /// cache's [`Expiry`], or else for its default TTL.
+    ///
+    /// Fails with `TtlError::NoDefault` if neither gives a TTL.
+    pub fn insert_default(&self, key: K, value: V) -> Result<(), TtlError> {
+        self.insert_with_ttl(key, value, self.default_ttl)
+    }
 
-        // Insert the value into the cache.
-        {
-            let mut map = self.map.write().unwrap();
-            map.insert(key, expiring_value);
+    /// Inserts a key-value pair that lives until `deadline`, given as an
+    /// [`Instant`] or a [`SystemTime`]. The cache's [`Expiry`] is not
+    /// consulted. A deadline already past stores a value that has expired.
+    ///
+    /// A `SystemTime` deadline follows the wall clock: if the system clock
+    /// is adjusted, the value expires when the wall clock reaches the
+    /// deadline, not when the time left at insertion runs out.
+    ///
+    /// Fails without changing the cache if the deadline is too far off to
+    /// represent.
+    pub fn insert_until(&self, key: K, value: V, deadline: impl Into<Deadline>) -> Result<(), TtlError> {
+        let now = self.now();
+        let deadline = deadline.into();
+        let ttl = match deadline {
+            Deadline::Instant(instant) => instant.saturating_duration_since(now.instant),
+            Deadline::SystemTime(time) => time.duration_since(now.wall).unwrap_or_default(),
+        };
+        deadline.at(now).ok_or(TtlError::TooLong(ttl))?;
+        let entries = self.entries.write().unwrap();
+        self.store(entries, key, value, ttl, deadline, now);
+        Ok(())
+    }
+
+    /// Insert a value living for the TTL picked by the expiry hooks, falling
+    /// back to `ttl`.
+    fn insert_with_ttl(&self, key: K, value: V, ttl: Option<Duration>) -> Result<(), TtlError> {
+        let now = self.now();
+
+        let entries = self.entries.write().unwrap();
+        let ttl = ttl_on_write(self.expiry.as_deref(), &entries, &key, &value, now)
+            .or(ttl)
+            .ok_or(TtlError::NoDefault)?;
+        let deadline = deadline_after(now.instant, ttl)?;
+        self.store(entries, key, value, ttl, deadline, now);
+        Ok(())
+    }
+
+    /// Insert a value, then clean up whatever else has expired.
+    fn store(
+        &self,
+        mut entries: RwLockWriteGuard<'_, Entries<K, V>>,
+        key: K,
+        value: V,
+        ttl: Duration,
+        deadline: Deadline,
+        now: Now,
+    ) {
+        let mut removals = Removals::new();
+        if let Some(old) = entries.insert(key.clone(), value, ttl, deadline, now) {
+            let cause = if entries.expiry(&old, now) > now.instant {
+                RemovalCause::Replaced
+            } else {
+                RemovalCause::Expired
+            };
+            removals.push((key, old.value, cause));
         }
+        removals.extend(entries.remove_expired(now));
+        drop(entries);
+        listener::notify(self.listener.as_ref(), removals);
+    }
 
-        // Clean up expired entries after the insertion.
-        self.clean_expired();
+    /// Get the entry for a key, for in-place lookup and insertion. A value
+    /// inserted through the entry lives for `ttl` from when the entry was
+    /// taken, unless the cache's [`Expiry`] picks a TTL that can be
+    /// represented.
+    ///
+    /// The cache stays locked until the entry is consumed, so the closures
+    /// given to it must not use the cache.
+    pub fn entry(&self, key: K, ttl: Duration) -> Result<ExpireEntry<'_, K, V>, TtlError> {
+        let now = self.now();
+        let deadline = deadline_after(now.instant, ttl)?;
+        Ok(ExpireEntry {
+            entries: self.entries.write().unwrap(),
+            expiry: self.expiry.as_deref(),
+            listener: self.listener.as_ref(),
+            key,
+            now,
+            ttl,
+            deadline,
+        })
+    }
+
+    /// Get the value for `key`, computing it with `f` and inserting it for
+    /// `ttl` if it is not cached or has expired. The result tells whether
+    /// the value was a hit or a miss.
+    ///
+    /// `f` runs while the cache is locked, so concurrent callers wait for one
+    /// computation instead of repeating it; it must not use the cache.
+    pub fn get_or_insert_with<F>(&self, key: K, ttl: Duration, f: F) -> Result<Lookup<V>, TtlError>
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(Lookup::Hit(value));
+        }
+        match self.entry(key, ttl)?.resolve(|| Ok::<_, Infallible>(f())) {
+            Ok(lookup) => Ok(lookup),
+            Err(never) => match never {},
+        }
     }
 
     /// Retrieves the value associated with a key, if it has not expired.
-    pub fn get(&self, key: &K) -> Option<V> {
-        // Clean up expired entries before attempting retrieval.
+    /// Under a time-to-idle this counts as a use of the entry.
+    ///
+    /// A live key is read under a shared lock, so concurrent readers do not
+    /// block each other. Finding an expired value, or a new TTL from the
+    /// cache's [`Expiry`], takes the write lock.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        let (value, new_ttl) = {
+            let entries = self.entries.read().unwrap();
+            match entries.map.get_key_value(key) {
+                Some((stored_key, entry)) if entries.expiry(entry, now) > now.instant => {
+                    entries.touch(entry, now);
+                    let new_ttl = self.expiry.as_deref().and_then(|expiry| {
+                        let remaining = entries.expiry(entry, now) - now.instant;
+                        Some((expiry.expire_after_read(stored_key, &entry.value, remaining)?, entry.seq))
+                    });
+                    (entry.value.clone(), new_ttl)
+                }
+                Some(_) => {
+                    drop(entries);
+                    self.clean_expired();
+                    return None;
+                }
+                None => return None,
+            }
+        };
+        if let Some((ttl, seq)) = new_ttl {
+            let mut entries = self.entries.write().unwrap();
+            // Unless the value was replaced in between; a TTL too long to
+            // represent leaves the expiry as it was
+            if entries.map.get(key).is_some_and(|entry| entry.seq == seq) {
+                let _ = entries.restart(key, ttl, now);
+            }
+        }
+        Some(value)
+    }
+
+    /// Returns `true` if the key has a live value, without counting as a
+    /// read of it.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.entries.read().unwrap().live(key, now).is_some()
+    }
+
+    /// Remove a key from the cache, returning its value if it had not
+    /// expired.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        let (key, entry, live) = {
+            let mut entries = self.entries.write().unwrap();
+            let (key, entry) = entries.remove(key)?;
+            let live = entries.expiry(&entry, now) > now.instant;
+            (key, entry, live)
+        };
+        if !live {
+            listener::notify(self.listener.as_ref(), vec![(key, entry.value, RemovalCause::Expired)]);
+            return None;
+        }
+        if let Some(listener) = &self.listener {
+            listener(key, entry.value.clone(), RemovalCause::Explicit);
+        }
+        Some(entry.value)
+    }
+
+    /// Remove every entry, reporting live ones as `RemovalCause::Cleared`
+    /// and the rest as `RemovalCause::Expired`.
+    pub fn clear(&self) {
+        let now = self.now();
+        let removals: Removals<K, V> = {
+            let mut entries = self.entries.write().unwrap();
+            let drained = entries.clear();
+            drained
+                .into_iter()
+                .map(|(key, entry)| {
+                    let cause = if entries.expiry(&entry, now) > now.instant {
+                        RemovalCause::Cleared
+                    } else {
+                        RemovalCause::Expired
+                    };
+                    (key, entry.value, cause)
+                })
+                .collect()
+        };
+        listener::notify(self.listener.as_ref(), removals);
+    }
+
+    /// Number of live entries. Expired entries are removed first.
+    pub fn len(&self) -> usize {
         self.clean_expired();
+        self.entries.read().unwrap().map.len()
+    }
 
-        // Retrieve the value from the cache.
-        let map = self.map.read().unwrap();
-        map.get(key).and_then(|v| {
-            if v.expiry > Instant::now() {
-                Some(v.value.clone())
-            } else {
-                None
+    /// Returns `true` if the cache holds no live entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A snapshot of the live entries, in no particular order. Listing an
+    /// entry does not count as a read of it.
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        let now = self.now();
+        let entries = self.entries.read().unwrap();
+        let live: Vec<(K, V)> = entries
+            .map
+            .iter()
+            .filter(|(_, entry)| entries.expiry(entry, now) > now.instant)
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+        live.into_iter()
+    }
+
+    /// The live entries with their expiry on the wall clock, to be saved and
+    /// later handed to [`restore`](Self::restore), e.g. across a restart.
+    /// Entries inserted with a `SystemTime` deadline keep it exactly;
+    /// under a time-to-idle the expiry assumes no further reads.
+    pub fn persist(&self) -> Vec<PersistedEntry<K, V>> {
+        let now = self.now();
+        let entries = self.entries.read().unwrap();
+        entries
+            .map
+            .iter()
+            .filter_map(|(key, entry)| {
+                let expiry = entries.expiry(entry, now);
+                if expiry <= now.instant {
+                    return None;
+                }
+                let expires_at = match entry.deadline {
+                    Deadline::SystemTime(time) if entry.deadline.at(now) == Some(expiry) => time,
+                    _ => now.wall + (expiry - now.instant),
+                };
+                Some(PersistedEntry {
+                    key: key.clone(),
+                    value: entry.value.clone(),
+                    expires_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Insert entries saved by [`persist`](Self::persist), each living until
+    /// its wall-clock expiry. Entries that expired in the meantime are
+    /// skipped.
+    ///
+    /// Stops at the first expiry too far off to represent; the entries
+    /// before it stay inserted.
+    pub fn restore<I>(&self, persisted: I) -> Result<(), TtlError>
+    where
+        I: IntoIterator<Item = PersistedEntry<K, V>>,
+    {
+        for entry in persisted {
+            if entry.expires_at > self.clock.system_time() {
+                self.insert_until(entry.key, entry.value, entry.expires_at)?;
             }
-        })
+        }
+        Ok(())
+    }
+
+    /// How long the value for `key` has left to live, or `None` if there is
+    /// no live value. Under a time-to-idle this assumes it is not read again.
+    pub fn ttl_of<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        let entries = self.entries.read().unwrap();
+        let entry = entries.live(key, now)?;
+        Some(entries.expiry(entry, now) - now.instant)
+    }
+
+    /// Restart the TTL of a live value from now, as if it had just been
+    /// inserted, without replacing it. Returns `false` if there is no live
+    /// value.
+    pub fn touch<Q>(&self, key: &Q) -> Result<bool, TtlError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        let mut entries = self.entries.write().unwrap();
+        let Some(entry) = entries.live(key, now) else {
+            return Ok(false);
+        };
+        let ttl = entry.ttl;
+        entries.touch(entry, now);
+        entries.restart(key, ttl, now)?;
+        Ok(true)
+    }
+
+    /// Push the TTL of a live value back by `by`, without replacing it.
+    /// Returns `false` if there is no live value. Under a time-to-idle the
+    /// value still expires if it goes unread.
+    ///
+    /// Fails without changing the cache if the new deadline is too far off
+    /// to represent.
+    pub fn extend_ttl<Q>(&self, key: &Q, by: Duration) -> Result<bool, TtlError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        let mut entries = self.entries.write().unwrap();
+        let Some(entry) = entries.live(key, now) else {
+            return Ok(false);
+        };
+        let deadline = entry
+            .deadline
+            .checked_add(by)
+            .filter(|deadline| deadline.at(now).is_some())
+            .ok_or(TtlError::TooLong(by))?;
+        entries.set_deadline(key, deadline, now);
+        Ok(true)
     }
 
     fn clean_expired(&self) {
-        let mut map = self.map.write().unwrap();
-        let now = Instant::now();
-        map.retain(|_, v| v.expiry > now);
+        let removals = self.entries.write().unwrap().remove_expired(self.now());
+        listener::notify(self.listener.as_ref(), removals);
+    }
+
+    fn now(&self) -> Now {
+        Now::of(&self.clock)
+    }
+}
+
+impl<K, V, C> ExpireCache<K, V, C>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    C: Clock + 'static,
+{
+    /// Remove expired entries every `interval` on a background thread, so
+    /// an idle cache does not hold on to them. Each is reported to the
+    /// removal listener as `RemovalCause::Expired`.
+    ///
+    /// The reaper runs until the returned handle is dropped or the cache is.
+    pub fn start_reaper(self: &Arc<Self>, interval: Duration) -> ReaperHandle {
+        reaper::spawn_thread(interval, Self::sweeper(Arc::downgrade(self)))
+    }
+
+    /// Like [`start_reaper`](Self::start_reaper), but sweeps from a task on
+    /// the current Tokio runtime instead of a thread. Each sweep runs on the
+    /// runtime's blocking thread pool, so removal listeners may block.
+    ///
+    /// # Panics
+    /// Panics if called outside of a Tokio runtime.
+    #[cfg(feature = "async")]
+    pub fn start_async_reaper(self: &Arc<Self>, interval: Duration) -> ReaperHandle {
+        reaper::spawn_task(interval, Self::sweeper(Arc::downgrade(self)))
+    }
+
+    /// A reaper's sweep, holding `cache` weakly so the reaper does not keep
+    /// it alive; returns `false` once the cache is gone.
+    fn sweeper(cache: Weak<Self>) -> impl FnMut() -> bool + Send + 'static {
+        move || match cache.upgrade() {
+            Some(cache) => {
+                cache.clean_expired();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// TTL the expiry hooks pick for `value` written to `key` at `now`, if any.
+fn ttl_on_write<K, V>(
+    expiry: Option<&dyn Expiry<K, V>>,
+    entries: &Entries<K, V>,
+    key: &K,
+    value: &V,
+    now: Now,
+) -> Option<Duration>
+where
+    K: Hash + Eq + Clone,
+{
+    let expiry = expiry?;
+    match entries.live(key, now) {
+        Some(entry) => expiry.expire_after_update(key, value, entries.expiry(entry, now) - now.instant),
+        None => expiry.expire_after_create(key, value),
+    }
+}
+
+/// End of the TTL of a value inserted at `now`.
+pub(crate) fn deadline_after(now: Instant, ttl: Duration) -> Result<Deadline, TtlError> {
+    now.checked_add(ttl)
+        .map(Deadline::Instant)
+        .ok_or(TtlError::TooLong(ttl))
+}
+
+/// A single key of an [`ExpireCache`], holding the cache's write lock until
+/// it is consumed. Expired values count as vacant.
+pub struct ExpireEntry<'a, K, V> {
+    entries: RwLockWriteGuard<'a, Entries<K, V>>,
+    expiry: Option<&'a dyn Expiry<K, V>>,
+    listener: Option<&'a RemovalListener<K, V>>,
+    key: K,
+    /// When the entry was taken, which is when it checks for expiry.
+    now: Now,
+    ttl: Duration,
+    deadline: Deadline,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ExpireEntry<'_, K, V> {
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Modify the cached value in place if it is present and live.
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Some(entry) = self.entries.live(&self.key, self.now) {
+            self.entries.touch(entry, self.now);
+            let entry = self.entries.map.get_mut(&self.key).expect("live entry is mapped");
+            f(&mut entry.value);
+        }
+        self
+    }
+
+    /// Insert `default` if there is no live value, and return the cached value.
+    pub fn or_insert(self, default: V) -> V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Insert the result of `f` if there is no live value, and return the cached value.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> V {
+        match self.resolve(|| Ok::<_, Infallible>(f())) {
+            Ok(lookup) => lookup.into_inner(),
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like `or_insert_with`, but `f` may fail, in which case nothing is inserted.
+    pub fn or_try_insert_with<F, E>(self, f: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        self.resolve(f).map(Lookup::into_inner)
+    }
+
+    fn resolve<F, E>(mut self, f: F) -> Result<Lookup<V>, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        if let Some(entry) = self.entries.live(&self.key, self.now) {
+            self.entries.touch(entry, self.now);
+            let value = entry.value.clone();
+            let remaining = self.entries.expiry(entry, self.now) - self.now.instant;
+            if let Some(ttl) = self
+                .expiry
+                .and_then(|expiry| expiry.expire_after_read(&self.key, &value, remaining))
+            {
+                // A TTL too long to represent leaves the expiry as it was
+                let _ = self.entries.restart(&self.key, ttl, self.now);
+            }
+            return Ok(Lookup::Hit(value));
+        }
+
+        let value = f()?;
+        let (ttl, deadline) = ttl_on_write(self.expiry, &self.entries, &self.key, &value, self.now)
+            .and_then(|ttl| Some((ttl, deadline_after(self.now.instant, ttl).ok()?)))
+            .unwrap_or((self.ttl, self.deadline));
+        let expired = self
+            .entries
+            .insert(self.key.clone(), value.clone(), ttl, deadline, self.now);
+        drop(self.entries);
+        if let Some(old) = expired {
+            listener::notify(self.listener, vec![(self.key, old.value, RemovalCause::Expired)]);
+        }
+        Ok(Lookup::Miss(value))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
     use std::thread::sleep;
 
+    const FIVE_SECONDS: Duration = Duration::from_secs(5);
+
     #[test]
     fn test_expire_cache() {
-        let cache = ExpireCache::new();
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone());
 
-        cache.insert("key1", "value1", 2); 
-        cache.insert("key2", "value2", 1);
+        cache.insert("key1", "value1", Duration::from_secs(2)).unwrap();
+        cache.insert("key2", "value2", Duration::from_secs(1)).unwrap();
 
         assert_eq!(cache.get(&"key1"), Some("value1"));
         assert_eq!(cache.get(&"key2"), Some("value2"));
 
-        sleep(StdDuration::from_secs(1));
-        assert_eq!(cache.get(&"key1"), Some("value1")); 
-        assert_eq!(cache.get(&"key2"), None);         
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&"key1"), Some("value1"));
+        assert_eq!(cache.get(&"key2"), None);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&"key1"), None);
+    }
+
+    #[test]
+    fn borrowed_key_lookups() {
+        let cache: ExpireCache<String, u32> = ExpireCache::new();
+        cache.insert("a".to_string(), 1, Duration::from_secs(5)).unwrap();
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("b"), None);
+    }
+
+    #[test]
+    fn entry_api() {
+        let cache: ExpireCache<&str, u32> = ExpireCache::new();
+        assert_eq!(cache.entry("a", FIVE_SECONDS).unwrap().or_insert(1), 1);
+        assert_eq!(cache.entry("a", FIVE_SECONDS).unwrap().and_modify(|v| *v += 1).or_insert(0), 2);
+        assert_eq!(cache.get("a"), Some(2));
+
+        let failed = cache.entry("b", FIVE_SECONDS).unwrap().or_try_insert_with(|| Err("nope"));
+        assert_eq!(failed, Err("nope"));
+        assert_eq!(cache.entry("b", FIVE_SECONDS).unwrap().or_try_insert_with(|| Ok::<_, ()>(3)), Ok(3));
+
+        // An expired value counts as vacant
+        cache.insert("c", 1, Duration::ZERO).unwrap();
+        assert_eq!(cache.entry("c", FIVE_SECONDS).unwrap().or_insert_with(|| 4), 4);
+        assert_eq!(cache.get("c"), Some(4));
+    }
+
+    #[test]
+    fn get_or_insert_with_reports_hits() {
+        let cache = ExpireCache::new();
+        assert_eq!(cache.get_or_insert_with("a", FIVE_SECONDS, || 1), Ok(Lookup::Miss(1)));
+        assert_eq!(cache.get_or_insert_with("a", FIVE_SECONDS, || 2), Ok(Lookup::Hit(1)));
+    }
+
+    #[test]
+    fn removal_listener_sees_replacements_and_expiries() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let clock = MockClock::new();
+        let cache = Arc::new_cyclic(|weak: &std::sync::Weak<ExpireCache<&str, u32, MockClock>>| {
+            let weak = weak.clone();
+            ExpireCache::with_clock(clock.clone()).with_removal_listener(move |key, value, cause| {
+                // Would deadlock if the map were still locked
+                weak.upgrade().unwrap().get(&key);
+                sink.lock().unwrap().push((key, value, cause));
+            })
+        });
+
+        cache.insert("a", 1, Duration::from_secs(1)).unwrap();
+        cache.insert("b", 2, Duration::ZERO).unwrap();
+        cache.insert("a", 10, Duration::from_secs(1)).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&"a"), None);
+
+        let mut log = log.lock().unwrap().clone();
+        log.sort_by_key(|(key, value, _)| (*key, *value));
+        assert_eq!(
+            log,
+            vec![
+                ("a", 1, RemovalCause::Replaced),
+                ("a", 10, RemovalCause::Expired),
+                ("b", 2, RemovalCause::Expired),
+            ]
+        );
+    }
+
+    #[test]
+    fn ttls_are_not_capped() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone());
+        let day = Duration::from_secs(24 * 60 * 60);
+        cache.insert("config", 1, day).unwrap();
+        cache.insert("token", 2, Duration::from_millis(50)).unwrap();
+        assert_eq!(cache.get("config"), Some(1));
+        assert_eq!(cache.get("token"), Some(2));
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(cache.get("config"), Some(1));
+        assert_eq!(cache.get("token"), None);
+        clock.advance(day);
+        assert_eq!(cache.get("config"), None);
+    }
+
+    #[test]
+    fn default_ttl_is_used_by_insert_default() {
+        let cache = ExpireCache::with_default_ttl(FIVE_SECONDS);
+        assert_eq!(cache.default_ttl(), Some(FIVE_SECONDS));
+        cache.insert_default("a", 1).unwrap();
+        assert_eq!(cache.get("a"), Some(1));
+
+        let clock = MockClock::new();
+        let mocked = ExpireCache::with_default_ttl_and_clock(FIVE_SECONDS, clock.clone());
+        mocked.insert_default("a", 1).unwrap();
+        clock.advance(FIVE_SECONDS);
+        assert_eq!(mocked.get("a"), None);
+
+        let no_default: ExpireCache<&str, u32> = ExpireCache::new();
+        assert_eq!(no_default.insert_default("a", 1), Err(TtlError::NoDefault));
+        assert_eq!(no_default.get("a"), None);
+    }
+
+    #[test]
+    fn unrepresentable_ttls_are_errors() {
+        let cache = ExpireCache::new();
+        assert_eq!(cache.insert("a", 1, Duration::MAX), Err(TtlError::TooLong(Duration::MAX)));
+        assert!(cache.entry("a", Duration::MAX).is_err());
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn expiry_index_follows_overwrites() {
+        let cache = ExpireCache::new();
+        cache.insert("a", 1, Duration::ZERO).unwrap();
+        cache.insert("a", 2, FIVE_SECONDS).unwrap();
+        cache.insert("b", 3, Duration::ZERO).unwrap();
+        assert_eq!(cache.get("a"), Some(2));
+        assert_eq!(cache.get("b"), None);
+
+        let entries = cache.entries.read().unwrap();
+        assert_eq!(entries.map.len(), 1);
+        assert_eq!(entries.expiries.len(), 1);
+    }
+
+    #[test]
+    fn cleanup_removes_due_entries_soonest_first() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone())
+            .with_removal_listener(move |key, _, _| sink.lock().unwrap().push(key));
+        cache.insert("late", 1, Duration::from_millis(20)).unwrap();
+        cache.insert("early", 2, Duration::from_millis(10)).unwrap();
+        cache.insert("live", 3, FIVE_SECONDS).unwrap();
+        clock.advance(Duration::from_millis(20));
+        cache.insert("new", 4, FIVE_SECONDS).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["early", "late"]);
+    }
+
+    #[test]
+    fn reads_keep_idle_entries_alive() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone()).with_time_to_idle(Duration::from_secs(10));
+        assert_eq!(cache.time_to_idle(), Some(Duration::from_secs(10)));
+        cache.insert("session", 1, Duration::from_secs(3600)).unwrap();
+        cache.insert("unread", 2, Duration::from_secs(3600)).unwrap();
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(8));
+            assert_eq!(cache.get("session"), Some(1));
+        }
+        assert_eq!(cache.get("unread"), None);
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.get("session"), None);
+    }
+
+    #[test]
+    fn ttl_caps_the_lifetime_of_read_entries() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone()).with_time_to_idle(Duration::from_secs(10));
+        cache.insert("a", 1, Duration::from_secs(20)).unwrap();
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(cache.get("a"), Some(1));
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(cache.get("a"), Some(1));
+        // Read 4 seconds ago, but 20 seconds old
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn cleanup_refiles_entries_kept_alive_by_reads() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone())
+            .with_time_to_idle(Duration::from_secs(10))
+            .with_removal_listener(move |key, _, cause| sink.lock().unwrap().push((key, cause)));
+        cache.insert("read", 1, FIVE_SECONDS * 100).unwrap();
+        cache.insert("idle", 2, FIVE_SECONDS * 100).unwrap();
+        clock.advance(Duration::from_secs(6));
+        cache.get("read");
+
+        // Both were filed to expire now, but only one went unread
+        clock.advance(Duration::from_secs(4));
+        cache.insert("new", 3, FIVE_SECONDS).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec![("idle", RemovalCause::Expired)]);
+        let entries = cache.entries.read().unwrap();
+        assert_eq!(entries.map.len(), entries.expiries.len());
+        assert_eq!(entries.map["read"].indexed, clock.now() + Duration::from_secs(6));
+    }
+
+    #[test]
+    fn management_api_sees_only_live_entries() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone());
+        cache.insert("a", 1, FIVE_SECONDS).unwrap();
+        cache.insert("b", 2, FIVE_SECONDS * 2).unwrap();
+        cache.insert("c", 3, Duration::from_secs(1)).unwrap();
+        assert_eq!(cache.len(), 3);
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.is_empty());
+        assert!(cache.contains_key("a"));
+        assert!(!cache.contains_key("c"));
+        let mut live: Vec<_> = cache.iter().collect();
+        live.sort_unstable();
+        assert_eq!(live, vec![("a", 1), ("b", 2)]);
+        assert_eq!(cache.ttl_of("a"), Some(Duration::from_secs(4)));
+        assert_eq!(cache.ttl_of("c"), None);
+
+        assert_eq!(cache.remove("a"), Some(1));
+        assert_eq!(cache.remove("a"), None);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.get("b"), None);
+    }
 
-        sleep(StdDuration::from_secs(1));
-        assert_eq!(cache.get(&"key1"), None);          
+    #[test]
+    fn removal_causes_of_remove_and_clear() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone())
+            .with_removal_listener(move |key, _, cause| sink.lock().unwrap().push((key, cause)));
+        cache.insert("a", 1, FIVE_SECONDS).unwrap();
+        cache.insert("b", 2, Duration::from_secs(1)).unwrap();
+        cache.insert("c", 3, Duration::from_secs(1)).unwrap();
+        cache.insert("d", 4, FIVE_SECONDS).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.remove("a"), Some(1));
+        assert_eq!(cache.remove("b"), None);
+        cache.clear();
+
+        let mut log = log.lock().unwrap().clone();
+        log[2..].sort_by_key(|(key, _)| *key);
+        assert_eq!(
+            log,
+            vec![
+                ("a", RemovalCause::Explicit),
+                ("b", RemovalCause::Expired),
+                ("c", RemovalCause::Expired),
+                ("d", RemovalCause::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn touch_and_extend_ttl_push_expiry_back() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone());
+        cache.insert("touched", 1, FIVE_SECONDS).unwrap();
+        cache.insert("extended", 2, FIVE_SECONDS).unwrap();
+        cache.insert("plain", 3, FIVE_SECONDS).unwrap();
+        clock.advance(Duration::from_secs(4));
+
+        assert_eq!(cache.touch("touched"), Ok(true));
+        assert_eq!(cache.ttl_of("touched"), Some(FIVE_SECONDS));
+        assert_eq!(cache.extend_ttl("extended", Duration::from_secs(10)), Ok(true));
+        assert_eq!(cache.ttl_of("extended"), Some(Duration::from_secs(11)));
+        assert_eq!(cache.extend_ttl("extended", Duration::MAX), Err(TtlError::TooLong(Duration::MAX)));
+        assert_eq!(cache.touch("missing"), Ok(false));
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(cache.get("touched"), Some(1));
+        assert_eq!(cache.get("extended"), Some(2));
+        assert_eq!(cache.get("plain"), None);
+        assert_eq!(cache.touch("plain"), Ok(false));
+        let entries = cache.entries.read().unwrap();
+        assert_eq!(entries.map.len(), entries.expiries.len());
+    }
+
+    #[test]
+    fn insert_until_takes_instants_and_system_times() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone()).with_expiry(MaxAge);
+        cache.insert_until("instant", Ok(60), clock.now() + FIVE_SECONDS).unwrap();
+        cache.insert_until("wall", Ok(60), clock.system_time() + Duration::from_secs(10)).unwrap();
+        cache.insert_until("past", Ok(60), clock.system_time() - FIVE_SECONDS).unwrap();
+
+        // The deadline wins over the Expiry's TTL
+        assert_eq!(cache.ttl_of("instant"), Some(FIVE_SECONDS));
+        assert_eq!(cache.ttl_of("wall"), Some(Duration::from_secs(10)));
+        assert_eq!(cache.get("past"), None);
+
+        clock.advance(FIVE_SECONDS);
+        assert_eq!(cache.get("instant"), None);
+        assert_eq!(cache.get("wall"), Some(Ok(60)));
+        clock.advance(FIVE_SECONDS);
+        assert_eq!(cache.get("wall"), None);
+    }
+
+    #[test]
+    fn system_time_deadlines_follow_wall_clock_adjustments() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone());
+        let wall = clock.system_time();
+        cache.insert_until("wall", 1, wall + Duration::from_secs(60)).unwrap();
+        cache.insert("ttl", 2, Duration::from_secs(60)).unwrap();
+
+        // Setting the clock forward expires the wall deadline early, but
+        // not the TTL
+        clock.set_system_time(wall + Duration::from_secs(90));
+        assert_eq!(cache.get("wall"), None);
+        assert_eq!(cache.get("ttl"), Some(2));
+
+        cache.insert_until("wall", 1, wall + Duration::from_secs(120)).unwrap();
+        // Setting it back keeps the value past its original time left;
+        // cleanup refiles it instead of removing it
+        clock.set_system_time(wall);
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.ttl_of("wall"), Some(Duration::from_secs(59)));
+        assert_eq!(cache.extend_ttl("wall", FIVE_SECONDS), Ok(true));
+        assert_eq!(cache.ttl_of("wall"), Some(Duration::from_secs(64)));
+        clock.advance(Duration::from_secs(64));
+        assert_eq!(cache.get("wall"), None);
+        let entries = cache.entries.read().unwrap();
+        assert_eq!(entries.map.len(), entries.expiries.len());
+    }
+
+    #[test]
+    fn cleanup_sweeps_wall_deadlines_passed_by_a_clock_adjustment() {
+        let clock = MockClock::new();
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let cache = ExpireCache::with_clock(clock.clone())
+            .with_removal_listener(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+        let wall = clock.system_time();
+        cache.insert_until("token", 1, wall + Duration::from_secs(3600)).unwrap();
+
+        // The entry is filed an hour out on the monotonic clock, which has
+        // not moved
+        clock.set_system_time(wall + Duration::from_secs(7200));
+        assert_eq!(cache.len(), 0);
+        assert!(cache.is_empty());
+        assert_eq!(*log.lock().unwrap(), vec![("token", 1, RemovalCause::Expired)]);
+        let entries = cache.entries.read().unwrap();
+        assert!(entries.map.is_empty());
+        assert!(entries.expiries.is_empty());
+        assert!(entries.wall_deadlines.is_empty());
+    }
+
+    #[test]
+    fn persist_and_restore_keep_wall_clock_expiries() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone());
+        let wall = clock.system_time();
+        cache.insert("ttl", 1, Duration::from_secs(10)).unwrap();
+        cache.insert_until("wall", 2, wall + Duration::from_secs(30)).unwrap();
+        cache.insert("expired", 3, Duration::from_secs(1)).unwrap();
+        clock.advance(Duration::from_secs(2));
+
+        let mut persisted = cache.persist();
+        persisted.sort_by_key(|entry| entry.key);
+        assert_eq!(
+            persisted,
+            vec![
+                PersistedEntry { key: "ttl", value: 1, expires_at: wall + Duration::from_secs(10) },
+                PersistedEntry { key: "wall", value: 2, expires_at: wall + Duration::from_secs(30) },
+            ]
+        );
+
+        // A new process, started after the first entry expired
+        let clock = MockClock::new();
+        clock.set_system_time(wall + Duration::from_secs(20));
+        let restored = ExpireCache::with_clock(clock.clone());
+        restored.restore(persisted).unwrap();
+        assert_eq!(restored.get("ttl"), None);
+        assert_eq!(restored.ttl_of("wall"), Some(Duration::from_secs(10)));
+    }
+
+    /// Upstream responses, which carry a max-age; failures are retried soon.
+    struct MaxAge;
+
+    impl Expiry<&'static str, Result<u64, ()>> for MaxAge {
+        fn expire_after_create(&self, _: &&'static str, value: &Result<u64, ()>) -> Option<Duration> {
+            match value {
+                Ok(max_age) => Some(Duration::from_secs(*max_age)),
+                Err(()) => Some(Duration::from_secs(1)),
+            }
+        }
+
+        fn expire_after_update(
+            &self,
+            key: &&'static str,
+            value: &Result<u64, ()>,
+            remaining: Duration,
+        ) -> Option<Duration> {
+            match value {
+                // A failed refresh keeps serving the old value's lifetime
+                Err(()) => Some(remaining),
+                Ok(_) => self.expire_after_create(key, value),
+            }
+        }
+    }
+
+    #[test]
+    fn expiry_picks_ttls_from_values() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone()).with_expiry(MaxAge);
+        cache.insert_default("ok", Ok(60)).unwrap();
+        cache.insert_default("err", Err(())).unwrap();
+        // The hook overrides the TTL passed in
+        cache.insert("short", Ok(2), FIVE_SECONDS * 100).unwrap();
+        assert_eq!(cache.ttl_of("ok"), Some(Duration::from_secs(60)));
+        assert_eq!(cache.ttl_of("err"), Some(Duration::from_secs(1)));
+        assert_eq!(cache.ttl_of("short"), Some(Duration::from_secs(2)));
+
+        clock.advance(Duration::from_secs(10));
+        cache.insert_default("ok", Err(())).unwrap();
+        assert_eq!(cache.ttl_of("ok"), Some(Duration::from_secs(50)));
+
+        let entry = cache.entry("new", FIVE_SECONDS).unwrap();
+        assert_eq!(entry.or_insert(Ok(30)), Ok(30));
+        assert_eq!(cache.ttl_of("new"), Some(Duration::from_secs(30)));
+    }
+
+    /// Keeps entries alive for a minute after every read.
+    struct ExtendOnRead;
+
+    impl Expiry<&'static str, u32> for ExtendOnRead {
+        fn expire_after_read(&self, _: &&'static str, _: &u32, _: Duration) -> Option<Duration> {
+            Some(Duration::from_secs(60))
+        }
+    }
+
+    #[test]
+    fn expiry_can_reset_ttl_on_read() {
+        let clock = MockClock::new();
+        let cache = ExpireCache::with_clock(clock.clone()).with_expiry(ExtendOnRead);
+        cache.insert("read", 1, FIVE_SECONDS).unwrap();
+        cache.insert("unread", 2, FIVE_SECONDS).unwrap();
+        assert_eq!(cache.get("read"), Some(1));
+        assert_eq!(cache.ttl_of("read"), Some(Duration::from_secs(60)));
+        clock.advance(FIVE_SECONDS);
+        assert!(cache.contains_key("read"));
+        assert!(!cache.contains_key("unread"));
+
+        // Hooks returning `None` fall back to the usual TTLs
+        assert_eq!(cache.insert_default("other", 3), Err(TtlError::NoDefault));
+        let entries = cache.entries.read().unwrap();
+        assert_eq!(entries.map.len(), entries.expiries.len());
+    }
+
+    /// Timing-sensitive, so only run on demand, with
+    /// `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn get_latency_stays_flat_as_the_cache_grows() {
+        const LOOKUPS: u64 = 100_000;
+
+        let cache = ExpireCache::with_default_ttl(Duration::from_secs(3600));
+        let mut inserted = 0;
+        let mut latencies = Vec::new();
+        for size in [1_000, 100_000, 2_000_000] {
+            while inserted < size {
+                cache.insert_default(inserted, inserted).unwrap();
+                inserted += 1;
+            }
+            let start = Instant::now();
+            for i in 0..LOOKUPS {
+                let key = i.wrapping_mul(7_919) % size;
+                assert_eq!(cache.get(&key), Some(key));
+            }
+            latencies.push(start.elapsed() / LOOKUPS as u32);
+        }
+        // A scan would be thousands of times slower at the largest size;
+        // allow for cache misses in a bigger table
+        assert!(latencies[2] < latencies[0] * 20, "{:?}", latencies);
+    }
+
+    /// Poll `done` until it holds, for up to a few seconds, so checks on a
+    /// background reaper do not depend on how soon it gets scheduled.
+    fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+        let give_up = Instant::now() + FIVE_SECONDS;
+        while !done() {
+            if Instant::now() > give_up {
+                return false;
+            }
+            sleep(Duration::from_millis(1));
+        }
+        true
+    }
+
+    #[test]
+    fn reaper_removes_expired_entries_of_an_idle_cache() {
+        let clock = MockClock::new();
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let cache = Arc::new(
+            ExpireCache::with_clock(clock.clone())
+                .with_removal_listener(move |key, value, cause| sink.lock().unwrap().push((key, value, cause))),
+        );
+        let reaper = cache.start_reaper(Duration::from_millis(1));
+        cache.insert("a", 1, Duration::from_secs(1)).unwrap();
+        cache.insert("b", 2, FIVE_SECONDS).unwrap();
+        clock.advance(Duration::from_secs(2));
+        assert!(wait_until(|| !log.lock().unwrap().is_empty()));
+        assert_eq!(*log.lock().unwrap(), vec![("a", 1, RemovalCause::Expired)]);
+        assert_eq!(cache.entries.read().unwrap().map.len(), 1);
+
+        // Once stopped, nothing sweeps the cache
+        reaper.stop();
+        cache.insert("c", 3, Duration::from_secs(1)).unwrap();
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(cache.entries.read().unwrap().map.len(), 2);
+    }
+
+    #[test]
+    fn reaper_stops_when_the_cache_is_dropped() {
+        let cache = Arc::new(ExpireCache::<u32, u32, _>::with_clock(MockClock::new()));
+        let reaper = cache.start_reaper(Duration::from_millis(1));
+        drop(cache);
+        match &reaper.worker {
+            Some(crate::reaper::Worker::Thread { thread, .. }) => assert!(wait_until(|| thread.is_finished())),
+            _ => unreachable!("a thread reaper was started"),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test(start_paused = true)]
+    async fn async_reaper_removes_expired_entries() {
+        let swept_on = Arc::new(std::sync::Mutex::new(None));
+        let sink = Arc::clone(&swept_on);
+        let cache = Arc::new(
+            ExpireCache::with_clock(crate::clock::TokioClock)
+                .with_removal_listener(move |_, _, _| *sink.lock().unwrap() = Some(std::thread::current().id())),
+        );
+        let _reaper = cache.start_async_reaper(Duration::from_millis(10));
+        cache.insert("a", 1, Duration::from_millis(20)).unwrap();
+        // Each step fires the reaper's timer, then waits in real time for
+        // the sweep it hands to the blocking pool
+        for _ in 0..1_000 {
+            if cache.entries.read().unwrap().map.is_empty() {
+                break;
+            }
+            tokio::time::advance(Duration::from_millis(10)).await;
+            tokio::task::spawn_blocking(|| sleep(Duration::from_millis(1))).await.unwrap();
+        }
+        assert!(cache.entries.read().unwrap().map.is_empty());
+        // The sweep and its listener stayed off the runtime's thread
+        assert_ne!(*swept_on.lock().unwrap(), Some(std::thread::current().id()));
+        assert!(swept_on.lock().unwrap().is_some());
     }
 }
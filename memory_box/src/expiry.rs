@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+/// Computes per-entry TTLs for an [`ExpireCache`](crate::ExpireCache), e.g.
+/// from a max-age carried by the value, or to expire errors sooner than
+/// successes.
+///
+/// Each hook returns the entry's new TTL, counted from now, or `None` to
+/// keep the cache's usual behaviour. The hooks run while the cache is
+/// locked, so they must not use it.
+pub trait Expiry<K, V>: Send + Sync {
+    /// TTL for a value inserted under a key with no live value. `None`
+    /// uses the TTL passed to the insert, or the cache's default TTL.
+    fn expire_after_create(&self, _key: &K, _value: &V) -> Option<Duration> {
+        None
+    }
+
+    /// TTL for a value replacing a live one, which had `remaining` left to
+    /// live. `None` uses the TTL passed to the insert, or the cache's
+    /// default TTL.
+    fn expire_after_update(&self, _key: &K, _value: &V, _remaining: Duration) -> Option<Duration> {
+        None
+    }
+
+    /// TTL for a value after it is read, which had `remaining` left to
+    /// live. `None` leaves its expiry unchanged.
+    fn expire_after_read(&self, _key: &K, _value: &V, _remaining: Duration) -> Option<Duration> {
+        None
+    }
+}
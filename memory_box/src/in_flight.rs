@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::OnceCell;
+
+use crate::lookup::Lookup;
+
+/// Loads in progress, by key, so concurrent async `get_or_insert_with`
+/// calls for the same key wait for one computation instead of repeating it.
+///
+/// The map is only locked briefly and never across an await, so a call
+/// dropped mid-load can clean up after itself.
+pub(crate) struct InFlight<K, V> {
+    pub(crate) calls: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+/// A caller's share of the load for `key`. The last one to go, whether it
+/// finished or was cancelled, removes the load from the map, so a load
+/// nobody is waiting for does not stay behind.
+struct Call<'a, K: Hash + Eq, V> {
+    calls: &'a Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+    key: &'a K,
+    cell: Arc<OnceCell<V>>,
+}
+
+impl<K: Hash + Eq, V> Drop for Call<'_, K, V> {
+    fn drop(&mut self) {
+        let Ok(mut calls) = self.calls.lock() else {
+            return;
+        };
+        // Let go of the cell under the lock, so of two callers leaving at
+        // once the second sees the first gone
+        let cell = std::mem::take(&mut self.cell);
+        let running = calls.get(self.key).is_some_and(|running| Arc::ptr_eq(running, &cell));
+        let last = running && Arc::strong_count(&cell) == 2;
+        drop(cell);
+        if last {
+            calls.remove(self.key);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> InFlight<K, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `load` for `key`, unless a load for it is already running, in
+    /// which case wait for that one's value and report it as a hit.
+    ///
+    /// If the running load is cancelled, one of the waiting calls runs its
+    /// own `load` instead.
+    pub(crate) async fn load<F, Fut>(&self, key: &K, load: F) -> Lookup<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Lookup<V>>,
+    {
+        let call = {
+            let mut calls = self.calls.lock().unwrap();
+            let cell = calls.entry(key.clone()).or_default();
+            // Finished, but callers that waited on it have not left yet
+            if cell.initialized() {
+                *cell = Arc::default();
+            }
+            Call {
+                calls: &self.calls,
+                key,
+                cell: Arc::clone(cell),
+            }
+        };
+        let mut loaded = None;
+        let value = call
+            .cell
+            .get_or_init(|| async {
+                let lookup = load().await;
+                let value = lookup.value().clone();
+                loaded = Some(lookup);
+                value
+            })
+            .await
+            .clone();
+        loaded.unwrap_or(Lookup::Hit(value))
+    }
+}
@@ -0,0 +1,318 @@
+use crate::cache::Cache;
+use crate::policy::EvictionPolicy;
+use crate::slab::{Link, List, Slab, NIL};
+
+/// LFU Cache Implementation
+///
+/// A [`Cache`] that evicts the least frequently used entry, breaking ties
+/// by evicting the least recently used of them. Iteration runs from the most
+/// to the least frequently used entry.
+///
+/// Use [`Lfu::with_aging`] through [`Cache::with_policy`] to let old
+/// popularity decay.
+pub type LfuCache<K, V> = Cache<K, V, Lfu>;
+
+/// All slots that have been used the same number of times, most recent first.
+struct Bucket {
+    frequency: u64,
+    slots: List,
+}
+
+/// Evicts the least frequently used entry in O(1).
+///
+/// Slots are grouped into buckets of equal use count, and the buckets are
+/// kept in a list sorted by count. A hit moves the slot into the next
+/// bucket up, creating it if needed, so no operation ever searches.
+#[derive(Default)]
+pub struct Lfu {
+    /// Buckets from most to least frequently used.
+    buckets: List,
+    bucket_links: Vec<Link>,
+    bucket_store: Slab<Bucket>,
+    /// Bucket each slot is in, indexed by slot.
+    bucket_of: Vec<usize>,
+    slot_links: Vec<Link>,
+    /// Halve every use count after this many inserts and hits; 0 never ages.
+    aging_period: usize,
+    since_aging: usize,
+}
+
+impl Lfu {
+    /// An LFU policy that halves every use count after `period` inserts and
+    /// hits, so keys that were popular long ago can eventually be evicted.
+    ///
+    /// Halving merges buckets; slots from the busier bucket are then treated
+    /// as the more recently used ones.
+    pub fn with_aging(period: usize) -> Self {
+        Self {
+            aging_period: period,
+            ..Self::default()
+        }
+    }
+
+    fn new_bucket(&mut self, frequency: u64) -> usize {
+        self.bucket_store.insert(Bucket {
+            frequency,
+            slots: List::new(),
+        })
+    }
+
+    /// Put `slot` at the front of `bucket`.
+    fn enter(&mut self, slot: usize, bucket: usize) {
+        if slot >= self.bucket_of.len() {
+            self.bucket_of.resize(slot + 1, NIL);
+        }
+        self.bucket_of[slot] = bucket;
+        self.bucket_store
+            .get_mut(bucket)
+            .slots
+            .push_front(&mut self.slot_links, slot);
+    }
+
+    /// Take `slot` out of its bucket, dropping the bucket if it empties.
+    fn leave(&mut self, slot: usize) {
+        let bucket = self.bucket_of[slot];
+        let slots = &mut self.bucket_store.get_mut(bucket).slots;
+        slots.unlink(&mut self.slot_links, slot);
+        if slots.is_empty() {
+            self.buckets.unlink(&mut self.bucket_links, bucket);
+            self.bucket_store.remove(bucket);
+        }
+    }
+
+    /// Count an insert or hit and age the counts if the period is up.
+    fn tick(&mut self) {
+        if self.aging_period == 0 {
+            return;
+        }
+        self.since_aging += 1;
+        if self.since_aging >= self.aging_period {
+            self.since_aging = 0;
+            self.halve();
+        }
+    }
+
+    /// Halve every use count, merging buckets that end up equal.
+    fn halve(&mut self) {
+        let mut bucket = self.buckets.back();
+        // The surviving bucket with the highest count seen so far
+        let mut kept: Option<usize> = None;
+        while let Some(idx) = bucket {
+            bucket = self.buckets.prev(&self.bucket_links, idx);
+            let frequency = (self.bucket_store.get(idx).frequency / 2).max(1);
+            match kept {
+                Some(into) if self.bucket_store.get(into).frequency == frequency => {
+                    // Move the slots over oldest first so their order holds
+                    while let Some(slot) = self.bucket_store.get(idx).slots.back() {
+                        self.bucket_store
+                            .get_mut(idx)
+                            .slots
+                            .unlink(&mut self.slot_links, slot);
+                        self.enter(slot, into);
+                    }
+                    self.buckets.unlink(&mut self.bucket_links, idx);
+                    self.bucket_store.remove(idx);
+                }
+                _ => {
+                    self.bucket_store.get_mut(idx).frequency = frequency;
+                    kept = Some(idx);
+                }
+            }
+        }
+    }
+}
+
+impl EvictionPolicy for Lfu {
+    fn on_insert(&mut self, slot: usize) {
+        let bucket = match self.buckets.back() {
+            Some(last) if self.bucket_store.get(last).frequency == 1 => last,
+            _ => {
+                let bucket = self.new_bucket(1);
+                self.buckets.push_back(&mut self.bucket_links, bucket);
+                bucket
+            }
+        };
+        self.enter(slot, bucket);
+        self.tick();
+    }
+
+    fn on_access(&mut self, slot: usize) {
+        let current = self.bucket_of[slot];
+        let frequency = self.bucket_store.get(current).frequency.saturating_add(1);
+        // The next bucket up sits just before the current one
+        let target = match self.buckets.prev(&self.bucket_links, current) {
+            Some(up) if self.bucket_store.get(up).frequency == frequency => up,
+            _ => {
+                let bucket = self.new_bucket(frequency);
+                self.buckets.insert_before(&mut self.bucket_links, current, bucket);
+                bucket
+            }
+        };
+        self.leave(slot);
+        self.enter(slot, target);
+        self.tick();
+    }
+
+    fn on_remove(&mut self, slot: usize) {
+        self.leave(slot);
+    }
+
+    fn victim(&self) -> Option<usize> {
+        let bucket = self.buckets.back()?;
+        self.bucket_store.get(bucket).slots.back()
+    }
+
+    fn first(&self) -> Option<usize> {
+        let bucket = self.buckets.front()?;
+        self.bucket_store.get(bucket).slots.front()
+    }
+
+    fn next(&self, slot: usize) -> Option<usize> {
+        let bucket = self.bucket_of[slot];
+        self.bucket_store
+            .get(bucket)
+            .slots
+            .next(&self.slot_links, slot)
+            .or_else(|| {
+                let lower = self.buckets.next(&self.bucket_links, bucket)?;
+                self.bucket_store.get(lower).slots.front()
+            })
+    }
+
+    fn last(&self) -> Option<usize> {
+        self.victim()
+    }
+
+    fn prev(&self, slot: usize) -> Option<usize> {
+        let bucket = self.bucket_of[slot];
+        self.bucket_store
+            .get(bucket)
+            .slots
+            .prev(&self.slot_links, slot)
+            .or_else(|| {
+                let higher = self.buckets.prev(&self.bucket_links, bucket)?;
+                self.bucket_store.get(higher).slots.back()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(cache: &LfuCache<u32, u32>) -> Vec<u32> {
+        cache.keys().copied().collect()
+    }
+
+    #[test]
+    fn evicts_the_least_frequently_used() {
+        let mut cache = LfuCache::new(2);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.get(&2), Some(2));
+        cache.insert(3, 3);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(keys(&cache), vec![1, 3]);
+    }
+
+    #[test]
+    fn ties_are_broken_by_recency() {
+        let mut cache = LfuCache::new(3);
+        for key in 1..=3 {
+            cache.insert(key, key);
+        }
+        // Every key is used twice, 2 longest ago
+        for key in [2, 1, 3] {
+            cache.get(&key);
+        }
+        cache.insert(4, 4);
+        assert!(!cache.contains_key(&2));
+        // 4 is the only key used once, so it goes next
+        assert_eq!(cache.peek_victim(), Some((&4, &4)));
+    }
+
+    #[test]
+    fn hot_keys_survive_a_scan() {
+        let mut cache = LfuCache::new(4);
+        for hot in 0..2 {
+            cache.insert(hot, hot);
+            for _ in 0..3 {
+                cache.get(&hot);
+            }
+        }
+        for cold in 100..200 {
+            cache.insert(cold, cold);
+        }
+        assert!(cache.contains_key(&0));
+        assert!(cache.contains_key(&1));
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn updates_count_as_uses() {
+        let mut cache = LfuCache::new(2);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.insert(1, 10);
+        cache.insert(3, 3);
+        assert_eq!(keys(&cache), vec![1, 3]);
+        assert_eq!(cache.peek(&1), Some(&10));
+    }
+
+    #[test]
+    fn iterates_both_ways_across_buckets() {
+        let mut cache = LfuCache::new(4);
+        for key in 1..=4 {
+            cache.insert(key, key);
+        }
+        cache.get(&3);
+        cache.get(&3);
+        cache.get(&1);
+        assert_eq!(keys(&cache), vec![3, 1, 4, 2]);
+        let backward: Vec<u32> = cache.keys().rev().copied().collect();
+        assert_eq!(backward, vec![2, 4, 1, 3]);
+
+        cache.remove(&1);
+        cache.remove(&3);
+        assert_eq!(keys(&cache), vec![4, 2]);
+    }
+
+    #[test]
+    fn aging_lets_old_favourites_go() {
+        let mut cache = Cache::with_policy(2, Lfu::with_aging(4));
+        cache.insert(1, 1);
+        for _ in 0..20 {
+            cache.get(&1);
+        }
+        // Without aging, 1 would be used 21 times and 2 could never win
+        cache.insert(2, 2);
+        for _ in 0..8 {
+            cache.get(&2);
+        }
+        cache.insert(3, 3);
+        assert!(cache.contains_key(&2));
+        assert!(!cache.contains_key(&1));
+    }
+
+    #[test]
+    fn growing_an_entry_keeps_its_frequency() {
+        let mut cache = LfuCache::with_weigher(10, |_: &u32, v: &Vec<u8>| v.len());
+        cache.insert(1, vec![0; 2]);
+        cache.get(&1);
+        cache.get(&1);
+        cache.insert(2, vec![0; 2]);
+        cache.get(&2);
+        cache.insert(3, vec![0; 2]);
+
+        // Making room for the larger value evicts 3, and 1 stays the most used
+        cache.insert(1, vec![0; 8]);
+        assert!(!cache.contains_key(&3));
+        let keys: Vec<u32> = cache.keys().copied().collect();
+        assert_eq!(keys, vec![1, 2]);
+        cache.insert(4, vec![0; 2]);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+    }
+}
@@ -1,5 +1,58 @@
+mod slab;
+mod cache;
+mod policy;
 mod lru_cache;
+mod lfu_cache;
+mod arc_cache;
+mod bounded_cache;
+mod sketch;
+mod tiny_lfu_cache;
+mod clock_cache;
+mod s3_fifo_cache;
+mod slru_cache;
+mod two_queue_cache;
+mod sharded_lru_cache;
+mod clock;
+mod error;
 mod expire_cache;
+mod expiry;
+mod ttl_lru_cache;
+mod weigher;
+mod listener;
+mod lookup;
+mod reaper;
+#[cfg(feature = "async")]
+mod in_flight;
+#[cfg(feature = "async")]
+mod async_lru_cache;
+#[cfg(feature = "async")]
+mod async_expire_cache;
 
+pub use cache::{Cache, Entry, Iter, OccupiedEntry, VacantEntry};
+pub use policy::{EvictionPolicy, Fifo, Lru, Mru, Random};
 pub use lru_cache::LruCache;
-pub use expire_cache::ExpireCache;
+pub use lfu_cache::{Lfu, LfuCache};
+pub use arc_cache::ArcCache;
+pub use bounded_cache::BoundedCache;
+pub use sketch::{CountMinSketch, Doorkeeper};
+pub use tiny_lfu_cache::TinyLfuCache;
+pub use clock_cache::ClockCache;
+pub use s3_fifo_cache::S3FifoCache;
+pub use slru_cache::{Slru, SlruCache};
+pub use two_queue_cache::TwoQueueCache;
+pub use sharded_lru_cache::ShardedLruCache;
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "async")]
+pub use clock::TokioClock;
+pub use error::TtlError;
+pub use expire_cache::{Deadline, ExpireCache, ExpireEntry, PersistedEntry};
+pub use expiry::Expiry;
+pub use ttl_lru_cache::TtlLruCache;
+pub use weigher::{UnitWeigher, Weigher};
+pub use listener::RemovalCause;
+pub use lookup::Lookup;
+pub use reaper::ReaperHandle;
+#[cfg(feature = "async")]
+pub use async_lru_cache::AsyncLruCache;
+#[cfg(feature = "async")]
+pub use async_expire_cache::AsyncExpireCache;
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+/// Why an entry left a cache, as reported to a removal listener.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RemovalCause {
+    /// Evicted to make room under the cache's capacity.
+    Evicted,
+    /// Its time-to-live ran out.
+    Expired,
+    /// Removed by a call to `remove`.
+    Explicit,
+    /// Overwritten by an `insert` for the same key.
+    Replaced,
+    /// Dropped by a call to `clear`.
+    Cleared,
+}
+
+/// Callback invoked with the key, value and cause of every removed entry.
+pub(crate) type RemovalListener<K, V> = Arc<dyn Fn(K, V, RemovalCause) + Send + Sync>;
+
+/// Entries removed under a lock, held until the lock is released so the
+/// listener can safely call back into the cache.
+pub(crate) type Removals<K, V> = Vec<(K, V, RemovalCause)>;
+
+/// Pass every removal to the listener, if there is one.
+pub(crate) fn notify<K, V>(listener: Option<&RemovalListener<K, V>>, removals: Removals<K, V>) {
+    if let Some(listener) = listener {
+        for (key, value, cause) in removals {
+            listener(key, value, cause);
+        }
+    }
+}
+
+/// A cache's removal listener plus the removals recorded for it, so a cache
+/// can record removals while it is mid-change and report them once it is
+/// consistent again.
+pub(crate) struct RemovalQueue<K, V> {
+    listener: Option<RemovalListener<K, V>>,
+    pending: Removals<K, V>,
+    /// Evictions collected for `resize` to return, while it runs.
+    evicted: Option<Vec<(K, V)>>,
+}
+
+impl<K: Clone, V: Clone> RemovalQueue<K, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            listener: None,
+            pending: Vec::new(),
+            evicted: None,
+        }
+    }
+
+    pub(crate) fn set_listener(&mut self, listener: RemovalListener<K, V>) {
+        self.listener = Some(listener);
+    }
+
+    /// Record a removal for the listener. Without one it is dropped, unless
+    /// it is an eviction being collected.
+    pub(crate) fn record(&mut self, key: K, value: V, cause: RemovalCause) {
+        if let (RemovalCause::Evicted, Some(evicted)) = (cause, &mut self.evicted) {
+            if self.listener.is_none() {
+                evicted.push((key, value));
+                return;
+            }
+            evicted.push((key.clone(), value.clone()));
+        }
+        if self.listener.is_some() {
+            self.pending.push((key, value, cause));
+        }
+    }
+
+    /// Like `record`, for a value the caller keeps; it is only cloned if
+    /// there is a listener.
+    pub(crate) fn record_cloned(&mut self, key: K, value: &V, cause: RemovalCause) {
+        if self.listener.is_some() {
+            self.pending.push((key, value.clone(), cause));
+        }
+    }
+
+    /// Start collecting evictions, e.g. for `resize` to return them.
+    pub(crate) fn collect_evictions(&mut self) {
+        self.evicted = Some(Vec::new());
+    }
+
+    /// Stop collecting evictions, returning them in eviction order.
+    pub(crate) fn take_evictions(&mut self) -> Vec<(K, V)> {
+        self.evicted.take().unwrap_or_default()
+    }
+
+    /// The removals recorded since the last call, for callers that must
+    /// release a lock before running the listener.
+    pub(crate) fn take(&mut self) -> Removals<K, V> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Pass the recorded removals to the listener.
+    pub(crate) fn notify(&mut self) {
+        if !self.pending.is_empty() {
+            let removals = self.take();
+            notify(self.listener.as_ref(), removals);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+/// Value returned by a get-or-insert call, tagged with where it came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lookup<V> {
+    /// The value was already cached.
+    Hit(V),
+    /// The value was computed by the call and inserted.
+    Miss(V),
+}
+
+impl<V> Lookup<V> {
+    /// Returns `true` if the value was already cached.
+    pub fn is_hit(&self) -> bool {
+        matches!(self, Lookup::Hit(_))
+    }
+
+    /// Returns `true` if the value was freshly computed.
+    pub fn is_miss(&self) -> bool {
+        matches!(self, Lookup::Miss(_))
+    }
+
+    /// A reference to the value, wherever it came from.
+    pub fn value(&self) -> &V {
+        match self {
+            Lookup::Hit(value) | Lookup::Miss(value) => value,
+        }
+    }
+
+    /// The value, wherever it came from.
+    pub fn into_inner(self) -> V {
+        match self {
+            Lookup::Hit(value) | Lookup::Miss(value) => value,
+        }
+    }
+}
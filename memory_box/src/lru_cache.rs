@@ -1,148 +1,64 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use crate::cache::Cache;
+use crate::policy::Lru;
 
 /// LRU Cache Implementation
-pub struct LruCache<K, V> {
-    map: HashMap<K, Arc<Mutex<Node<K, V>>>>,
-    head: Option<Arc<Mutex<Node<K, V>>>>,
-    tail: Option<Arc<Mutex<Node<K, V>>>>,
-    capacity: usize,
-    size: usize,
-}
+///
+/// A [`Cache`] that evicts the least recently used entry, so `get` and
+/// `insert` are O(1) and iteration runs from most to least recently used.
+pub type LruCache<K, V> = Cache<K, V, Lru>;
 
-/// Node of the doubly linked list
-struct Node<K, V> {
-    key: K,
-    value: V,
-    prev: Option<Arc<Mutex<Node<K, V>>>>,
-    next: Option<Arc<Mutex<Node<K, V>>>>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Entry;
+    use crate::listener::RemovalCause;
+    use crate::lookup::Lookup;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
 
-impl<K: std::hash::Hash + Eq + Clone, V: Clone> LruCache<K, V> {
-    /// Create a new LRU cache with a given capacity.
-    pub fn new(capacity: usize) -> Self {
-        Self {
-            map: HashMap::new(),
-            head: None,
-            tail: None,
-            capacity,
-            size: 0,
-        }
-    }
+    /// Counts live heap allocations made by the current thread, so the leak
+    /// tests are not disturbed by tests running in parallel.
+    struct CountingAlloc;
 
-    /// Insert a key-value pair into the cache.
-    pub fn insert(&mut self, key: K, value: V) {
-        if let Some(node) = self.map.remove(&key) {
-            // Key exists, update value and move node to the front
-            node.lock().unwrap().value = value;
-            self.move_to_front(node.clone());
-            self.map.insert(key, node);
-        } else {
-            // Key does not exist, create a new node
-            let new_node = Arc::new(Mutex::new(Node {
-                key: key.clone(),
-                value,
-                prev: None,
-                next: None,
-            }));
-
-            if self.size == self.capacity {
-                // Evict the least recently used item
-                self.evict();
-            } else {
-                self.size += 1;
-            }
-
-            // Add the new node to the front of the list
-            self.add_to_front(new_node.clone());
-            self.map.insert(key, new_node);
-        }
+    thread_local! {
+        static LIVE_ALLOCATIONS: Cell<isize> = const { Cell::new(0) };
     }
 
-    /// Get a value associated with a key.
-    pub fn get(&mut self, key: &K) -> Option<V> {
-        if let Some(node) = self.map.remove(key) {
-            self.move_to_front(node.clone());
-            let value = node.lock().unwrap().value.clone(); // Clone the value
-            self.map.insert(key.clone(), node); // Reinsert node
-            Some(value)
-        } else {
-            None
-        }
+    fn adjust_live(delta: isize) {
+        let _ = LIVE_ALLOCATIONS.try_with(|live| live.set(live.get() + delta));
     }
 
-    /// Remove a key-value pair from the cache.
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        if let Some(node) = self.map.remove(key) {
-            self.unlink(node.clone());
-            self.size -= 1;
-            // Safely extract the value
-            match Arc::try_unwrap(node) {
-                Ok(inner) => Some(inner.into_inner().unwrap().value),
-                Err(_) => None,
-            }
-        } else {
-            None
-        }
+    fn live_allocations() -> isize {
+        LIVE_ALLOCATIONS.with(|live| live.get())
     }
 
-    /// Add a node to the front of the doubly linked list.
-    fn add_to_front(&mut self, node: Arc<Mutex<Node<K, V>>>) {
-        {
-            let mut node_lock = node.lock().unwrap();
-            node_lock.next = self.head.clone();
-            node_lock.prev = None;
-        } // `node_lock` is dropped here
-    
-        if let Some(ref head) = self.head {
-            let mut head_lock = head.lock().unwrap();
-            head_lock.prev = Some(node.clone());
-        } else {
-            self.tail = Some(node.clone());
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            adjust_live(1);
+            System.alloc(layout)
         }
-    
-        self.head = Some(node);
-    }
-    
 
-    /// Move an existing node to the front of the list.
-    fn move_to_front(&mut self, node: Arc<Mutex<Node<K, V>>>) {
-        self.unlink(node.clone());
-        self.add_to_front(node);
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            adjust_live(-1);
+            System.dealloc(ptr, layout)
+        }
     }
 
-    /// Unlink a node from the doubly linked list.
-    fn unlink(&mut self, node: Arc<Mutex<Node<K, V>>>) {
-        let mut node_lock = node.lock().unwrap();
-        let prev = node_lock.prev.take();
-        let next = node_lock.next.take();
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
 
-        if let Some(ref prev_node) = prev {
-            prev_node.lock().unwrap().next = next.clone();
-        } else {
-            self.head = next.clone();
-        }
+    /// Value that bumps a shared counter when dropped.
+    #[derive(Clone)]
+    struct DropCounter(Rc<Cell<usize>>);
 
-        if let Some(ref next_node) = next {
-            next_node.lock().unwrap().prev = prev.clone();
-        } else {
-            self.tail = prev.clone();
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
         }
     }
 
-    /// Evict the least recently used item.
-    fn evict(&mut self) {
-        if let Some(tail_node) = self.tail.take() {
-            let key_to_remove = tail_node.lock().unwrap().key.clone();
-            self.remove(&key_to_remove);
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
     #[test]
     fn lru_cache_works() {
         let mut cache = LruCache::new(3);
@@ -164,4 +80,321 @@ mod tests {
         assert_eq!(cache.get(&3), Some("three"));
         assert_eq!(cache.get(&4), Some("four"));
     }
+
+    #[test]
+    fn remove_and_reuse_slots() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.remove(&"a"), Some(1));
+        assert_eq!(cache.remove(&"a"), None);
+
+        cache.insert("c", 3);
+        cache.insert("b", 20);
+        cache.insert("d", 4);
+
+        // "c" was least recently used once "b" was overwritten
+        assert_eq!(cache.get(&"c"), None);
+        assert_eq!(cache.get(&"b"), Some(20));
+        assert_eq!(cache.get(&"d"), Some(4));
+    }
+
+    #[test]
+    fn zero_capacity_stores_nothing() {
+        let mut cache = LruCache::new(0);
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn weigher_bounds_total_weight() {
+        let mut cache = LruCache::with_weigher(10, |_: &&str, v: &String| v.len());
+
+        cache.insert("a", "aaaa".to_string());
+        cache.insert("b", "bbbb".to_string());
+        assert_eq!(cache.total_weight(), 8);
+
+        // Needs 6, so only the oldest entry has to go
+        cache.insert("c", "cccccc".to_string());
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.total_weight(), 10);
+
+        cache.insert("d", "dd".to_string());
+        cache.insert("e", "ee".to_string());
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.total_weight(), 10);
+
+        // Growing an existing entry evicts others, never the entry itself
+        cache.insert("e", "eeeeee".to_string());
+        assert_eq!(cache.get(&"c"), None);
+        assert_eq!(cache.get(&"d"), Some("dd".to_string()));
+        assert_eq!(cache.get(&"e"), Some("eeeeee".to_string()));
+        assert_eq!(cache.total_weight(), 8);
+    }
+
+    #[test]
+    fn weigher_rejects_oversized_entries() {
+        let mut cache = LruCache::with_weigher(4, |_: &u32, v: &Vec<u8>| v.len());
+        cache.insert(1, vec![0; 2]);
+        cache.insert(2, vec![0; 5]);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(vec![0; 2]), "smaller entries are kept");
+
+        // Replacing with an oversized value drops the stale one
+        cache.insert(1, vec![0; 5]);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.total_weight(), 0);
+    }
+
+    #[test]
+    fn occupied_entry_insert_reweighs_in_place() {
+        let mut cache = LruCache::with_weigher(10, |_: &u32, v: &Vec<u8>| v.len());
+        cache.insert(1, vec![0; 2]);
+        cache.insert(2, vec![0; 4]);
+        let Entry::Occupied(mut entry) = cache.entry(1) else {
+            panic!("expected an occupied entry");
+        };
+
+        // Too heavy for the cache: handed back, the entry stays usable
+        assert_eq!(entry.insert(vec![0; 20]), Err(vec![0; 20]));
+        assert_eq!(entry.get(), &vec![0; 2]);
+
+        // Growing the entry evicts the other one, never the entry itself
+        assert_eq!(entry.insert(vec![0; 8]), Ok(vec![0; 2]));
+        assert_eq!(entry.get().len(), 8);
+        assert_eq!(entry.remove(), vec![0; 8]);
+        assert_eq!(cache.total_weight(), 0);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn vacant_entry_hands_back_values_it_cannot_store() {
+        let mut cache = LruCache::new(0);
+        assert_eq!(cache.entry(1).or_insert(1), Err(1));
+        assert_eq!(cache.entry(1).or_try_insert_with(|| Ok::<_, ()>(2)), Ok(Err(2)));
+        let Entry::Vacant(entry) = cache.entry(1) else {
+            panic!("expected a vacant entry");
+        };
+        assert_eq!(entry.insert(3), Err(3));
+        assert!(cache.is_empty());
+
+        let mut cache = LruCache::with_weigher(4, |_: &u32, v: &Vec<u8>| v.len());
+        cache.insert(1, vec![0; 2]);
+        assert_eq!(cache.entry(2).or_insert_with(|| vec![0; 5]), Err(vec![0; 5]));
+        assert_eq!(cache.entry(1).or_insert_with(|| vec![0; 5]), Ok(&mut vec![0; 2]));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn removal_listener_reports_causes() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = LruCache::new(2)
+            .with_removal_listener(move |k, v, cause| sink.lock().unwrap().push((k, v, cause)));
+
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        cache.insert(1, "uno");
+        cache.insert(3, "three");
+        assert_eq!(cache.remove(&1), Some("uno"));
+        assert_eq!(cache.remove(&1), None);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (1, "one", RemovalCause::Replaced),
+                (2, "two", RemovalCause::Evicted),
+                (1, "uno", RemovalCause::Explicit),
+            ]
+        );
+    }
+
+    #[test]
+    fn collecting_sizes_the_cache_by_distinct_keys() {
+        let cache: LruCache<_, _> = [(1, "a"), (1, "b"), (2, "c")].into_iter().collect();
+        assert_eq!(cache.capacity(), 2);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.peek(&1), Some(&"b"));
+    }
+
+    #[test]
+    fn inspection_does_not_change_order() {
+        let mut cache: LruCache<_, _> = [(1, "one"), (2, "two"), (3, "three")].into_iter().collect();
+        assert_eq!(cache.capacity(), 3);
+        assert_eq!(cache.len(), 3);
+
+        assert_eq!(cache.peek(&1), Some(&"one"));
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&4));
+        *cache.peek_mut(&2).unwrap() = "TWO";
+        assert_eq!(cache.peek_lru(), Some((&1, &"one")));
+
+        let keys: Vec<_> = cache.keys().copied().collect();
+        assert_eq!(keys, vec![3, 2, 1]);
+        let values: Vec<_> = cache.values().rev().copied().collect();
+        assert_eq!(values, vec!["one", "TWO", "three"]);
+
+        // Peeking left 1 as the least recently used entry
+        cache.insert(4, "four");
+        assert!(!cache.contains_key(&1));
+        assert_eq!(format!("{:?}", cache), r#"{4: "four", 3: "three", 2: "TWO"}"#);
+    }
+
+    #[test]
+    fn pop_lru_and_clear() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = LruCache::new(4)
+            .with_removal_listener(move |k, _, cause| sink.lock().unwrap().push((k, cause)));
+        cache.extend([(1, 'a'), (2, 'b'), (3, 'c')]);
+        cache.get(&1);
+
+        assert_eq!(cache.pop_lru(), Some((2, 'b')));
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.total_weight(), 0);
+        assert_eq!(cache.pop_lru(), None);
+        assert_eq!(cache.iter().next(), None);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (2, RemovalCause::Explicit),
+                (3, RemovalCause::Cleared),
+                (1, RemovalCause::Cleared),
+            ]
+        );
+
+        // The cache is still usable after being cleared
+        cache.insert(5, 'e');
+        assert_eq!(cache.get(&5), Some('e'));
+    }
+
+    #[test]
+    fn resize_keeps_the_most_recent_entries() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = LruCache::new(4)
+            .with_removal_listener(move |k, _, cause| sink.lock().unwrap().push((k, cause)));
+        cache.extend([(1, 'a'), (2, 'b'), (3, 'c'), (4, 'd')]);
+        cache.get(&1);
+
+        assert_eq!(cache.resize(2), vec![(2, 'b'), (3, 'c')]);
+        assert_eq!(cache.capacity(), 2);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![(2, RemovalCause::Evicted), (3, RemovalCause::Evicted)]
+        );
+
+        // Growing evicts nothing and makes room for more
+        assert!(cache.resize(3).is_empty());
+        cache.insert(5, 'e');
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn borrowed_key_lookups() {
+        let mut cache: LruCache<String, u32> = LruCache::new(2);
+        cache.insert("a".to_string(), 1);
+        cache.insert("b".to_string(), 2);
+
+        assert_eq!(cache.get("a"), Some(1));
+        assert!(cache.contains_key("b"));
+        assert_eq!(cache.peek("b"), Some(&2));
+        *cache.peek_mut("b").unwrap() += 1;
+        assert_eq!(cache.remove("b"), Some(3));
+        assert_eq!(cache.get("b"), None);
+    }
+
+    #[test]
+    fn entry_api() {
+        let mut cache: LruCache<&str, u32> = LruCache::new(2);
+        *cache.entry("a").or_insert(1).unwrap() += 10;
+        assert_eq!(cache.peek("a"), Some(&11));
+
+        cache.entry("a").and_modify(|v| *v += 1).or_insert(0).unwrap();
+        cache.entry("b").and_modify(|v| *v += 1).or_insert(5).unwrap();
+        assert_eq!(cache.peek("a"), Some(&12));
+        assert_eq!(cache.peek("b"), Some(&5));
+
+        let failed = cache.entry("c").or_try_insert_with(|| Err("nope"));
+        assert_eq!(failed, Err("nope"));
+        assert!(!cache.contains_key("c"));
+
+        // Touching "a" through the entry makes "b" the one to evict
+        assert_eq!(cache.entry("a").key(), &"a");
+        assert_eq!(cache.entry("c").or_insert_with(|| 7), Ok(&mut 7));
+        assert!(!cache.contains_key("b"));
+
+        match cache.entry("c") {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.insert(8), Ok(7));
+                assert_eq!(entry.remove(), 8);
+            }
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+        }
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn get_or_insert_with_reports_hits() {
+        let mut cache = LruCache::new(2);
+        let mut calls = 0;
+        let mut lookup = |cache: &mut LruCache<u32, u32>, key| {
+            cache.get_or_insert_with(key, || {
+                calls += 1;
+                key * 2
+            })
+        };
+
+        assert_eq!(lookup(&mut cache, 1), Lookup::Miss(2));
+        assert_eq!(lookup(&mut cache, 1), Lookup::Hit(2));
+        assert!(lookup(&mut cache, 2).is_miss());
+        assert_eq!(lookup(&mut cache, 2).into_inner(), 4);
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn values_are_dropped_exactly_once() {
+        let drops = Rc::new(Cell::new(0));
+        let mut cache = LruCache::new(4);
+
+        for i in 0..10 {
+            cache.insert(i, DropCounter(drops.clone()));
+        }
+        // Six entries were evicted to make room
+        assert_eq!(drops.get(), 6);
+
+        cache.insert(9, DropCounter(drops.clone()));
+        assert_eq!(drops.get(), 7, "replaced value is dropped");
+
+        drop(cache.remove(&8));
+        assert_eq!(drops.get(), 8);
+
+        drop(cache.get(&7));
+        assert_eq!(drops.get(), 9, "only the returned clone is dropped");
+
+        drop(cache);
+        assert_eq!(drops.get(), 12, "remaining three entries dropped with the cache");
+    }
+
+    #[test]
+    fn dropping_the_cache_frees_every_allocation() {
+        let before = live_allocations();
+        {
+            let mut cache = LruCache::new(64);
+            for i in 0..1_000 {
+                cache.insert(i.to_string(), vec![i; 8]);
+                if i % 3 == 0 {
+                    cache.get(&(i / 2).to_string());
+                }
+                if i % 7 == 0 {
+                    cache.remove(&(i - 1).to_string());
+                }
+            }
+            assert!(live_allocations() > before);
+        }
+        assert_eq!(live_allocations(), before);
+    }
 }
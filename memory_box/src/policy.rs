@@ -0,0 +1,299 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use crate::slab::{Link, List};
+
+/// Decides which entry a [`Cache`](crate::Cache) evicts when it is full.
+///
+/// The cache stores entries in numbered slots and tells the policy about
+/// every slot it fills, reads and frees; the policy keeps whatever ordering
+/// it needs and names the next victim. Slot numbers are small and reused
+/// once freed, so they can index a `Vec` directly.
+pub trait EvictionPolicy {
+    /// A new entry was stored in `slot`.
+    fn on_insert(&mut self, slot: usize);
+
+    /// The entry in `slot` was read through `get` or an entry handle.
+    fn on_access(&mut self, slot: usize);
+
+    /// The entry in `slot` left the cache, for any reason.
+    fn on_remove(&mut self, slot: usize);
+
+    /// The slot to evict next, or `None` if the cache is empty.
+    fn victim(&self) -> Option<usize>;
+
+    /// The slot to evict next other than `slot`, used to make room around
+    /// an entry whose value just grew.
+    ///
+    /// By default this is the victim, or else the slot next to `slot` on
+    /// the victim's side of the iteration order, which suits policies
+    /// that evict from one end of it.
+    fn victim_excluding(&self, slot: usize) -> Option<usize> {
+        match self.victim()? {
+            victim if victim != slot => Some(victim),
+            _ if self.last() == Some(slot) => self.prev(slot),
+            _ => self.next(slot),
+        }
+    }
+
+    /// The first slot in the policy's iteration order.
+    fn first(&self) -> Option<usize>;
+
+    /// The slot after `slot` in iteration order.
+    fn next(&self, slot: usize) -> Option<usize>;
+
+    /// The last slot in iteration order.
+    fn last(&self) -> Option<usize>;
+
+    /// The slot before `slot` in iteration order.
+    fn prev(&self, slot: usize) -> Option<usize>;
+}
+
+/// Slots kept in a list ordered from most to least recently touched.
+#[derive(Default)]
+struct RecencyList {
+    links: Vec<Link>,
+    list: List,
+}
+
+impl RecencyList {
+    fn push_front(&mut self, slot: usize) {
+        self.list.push_front(&mut self.links, slot);
+    }
+
+    fn move_to_front(&mut self, slot: usize) {
+        self.list.move_to_front(&mut self.links, slot);
+    }
+
+    fn leave_in_place(&mut self, _slot: usize) {}
+
+    fn unlink(&mut self, slot: usize) {
+        self.list.unlink(&mut self.links, slot);
+    }
+
+    fn front(&self) -> Option<usize> {
+        self.list.front()
+    }
+
+    fn back(&self) -> Option<usize> {
+        self.list.back()
+    }
+
+    fn next(&self, slot: usize) -> Option<usize> {
+        self.list.next(&self.links, slot)
+    }
+
+    fn prev(&self, slot: usize) -> Option<usize> {
+        self.list.prev(&self.links, slot)
+    }
+}
+
+/// Evicts the least recently used entry. Iterates from most to least recently used.
+#[derive(Default)]
+pub struct Lru {
+    order: RecencyList,
+}
+
+/// Evicts the most recently used entry, which suits cyclic scans larger than
+/// the cache. Iterates from most to least recently used.
+#[derive(Default)]
+pub struct Mru {
+    order: RecencyList,
+}
+
+/// Evicts the oldest entry, ignoring reads. Iterates from newest to oldest.
+#[derive(Default)]
+pub struct Fifo {
+    order: RecencyList,
+}
+
+macro_rules! list_policy {
+    ($policy:ty, access: $on_access:ident, victim: $victim:ident) => {
+        impl EvictionPolicy for $policy {
+            fn on_insert(&mut self, slot: usize) {
+                self.order.push_front(slot);
+            }
+
+            fn on_access(&mut self, slot: usize) {
+                self.order.$on_access(slot);
+            }
+
+            fn on_remove(&mut self, slot: usize) {
+                self.order.unlink(slot);
+            }
+
+            fn victim(&self) -> Option<usize> {
+                self.order.$victim()
+            }
+
+            fn first(&self) -> Option<usize> {
+                self.order.front()
+            }
+
+            fn next(&self, slot: usize) -> Option<usize> {
+                self.order.next(slot)
+            }
+
+            fn last(&self) -> Option<usize> {
+                self.order.back()
+            }
+
+            fn prev(&self, slot: usize) -> Option<usize> {
+                self.order.prev(slot)
+            }
+        }
+    };
+}
+
+list_policy!(Lru, access: move_to_front, victim: back);
+list_policy!(Mru, access: move_to_front, victim: front);
+list_policy!(Fifo, access: leave_in_place, victim: back);
+
+/// Evicts a pseudo-randomly chosen entry. Iterates in no particular order.
+pub struct Random {
+    slots: Vec<usize>,
+    positions: Vec<usize>,
+    state: u64,
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::with_seed(RandomState::new().hash_one(0u64))
+    }
+}
+
+impl Random {
+    /// A random policy whose choices are reproducible for a given seed.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            slots: Vec::new(),
+            positions: Vec::new(),
+            // xorshift gets stuck at zero
+            state: seed | 1,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+    }
+}
+
+impl EvictionPolicy for Random {
+    fn on_insert(&mut self, slot: usize) {
+        if slot >= self.positions.len() {
+            self.positions.resize(slot + 1, 0);
+        }
+        self.positions[slot] = self.slots.len();
+        self.slots.push(slot);
+        self.advance();
+    }
+
+    fn on_access(&mut self, _slot: usize) {}
+
+    fn on_remove(&mut self, slot: usize) {
+        let pos = self.positions[slot];
+        self.slots.swap_remove(pos);
+        if let Some(&moved) = self.slots.get(pos) {
+            self.positions[moved] = pos;
+        }
+        self.advance();
+    }
+
+    fn victim(&self) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let pick = (self.state >> 32) as usize % self.slots.len();
+        Some(self.slots[pick])
+    }
+
+    fn first(&self) -> Option<usize> {
+        self.slots.first().copied()
+    }
+
+    fn next(&self, slot: usize) -> Option<usize> {
+        self.slots.get(self.positions[slot] + 1).copied()
+    }
+
+    fn last(&self) -> Option<usize> {
+        self.slots.last().copied()
+    }
+
+    fn prev(&self, slot: usize) -> Option<usize> {
+        let pos = self.positions[slot];
+        pos.checked_sub(1).map(|pos| self.slots[pos])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+
+    fn keys<P: EvictionPolicy>(cache: &Cache<u32, u32, P>) -> Vec<u32> {
+        cache.keys().copied().collect()
+    }
+
+    #[test]
+    fn fifo_ignores_reads() {
+        let mut cache: Cache<u32, u32, Fifo> = Cache::new(2);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.get(&1), Some(1));
+        cache.insert(3, 3);
+        assert_eq!(keys(&cache), vec![3, 2]);
+    }
+
+    #[test]
+    fn mru_evicts_the_latest_read() {
+        let mut cache: Cache<u32, u32, Mru> = Cache::new(2);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.get(&1), Some(1));
+        cache.insert(3, 3);
+        assert_eq!(keys(&cache), vec![3, 2]);
+    }
+
+    #[test]
+    fn updating_a_key_keeps_it_under_mru() {
+        let mut cache: Cache<u32, u32, Mru> = Cache::new(2);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.insert(2, 20);
+        assert_eq!(keys(&cache), vec![2, 1]);
+        assert_eq!(cache.get(&2), Some(20));
+    }
+
+    #[test]
+    fn random_is_reproducible_and_bounded() {
+        let run = |seed| {
+            let mut cache = Cache::with_policy(8, Random::with_seed(seed));
+            for i in 0..100 {
+                cache.insert(i, i);
+            }
+            let mut kept = keys(&cache);
+            kept.sort_unstable();
+            kept
+        };
+        assert_eq!(run(7).len(), 8);
+        assert_eq!(run(7), run(7));
+        assert!(run(7).iter().all(|&k| k < 100));
+    }
+
+    #[test]
+    fn random_iterates_every_entry_both_ways() {
+        let mut cache = Cache::with_policy(4, Random::with_seed(1));
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        cache.remove(&1);
+        let forward = keys(&cache);
+        let mut backward: Vec<u32> = cache.keys().rev().copied().collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert_eq!(forward.len(), 3);
+        assert!(!forward.contains(&1));
+    }
+}
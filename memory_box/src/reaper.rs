@@ -0,0 +1,112 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Shortest interval between sweeps, so a zero interval cannot spin.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Keeps a background reaper running. Dropping it, or calling
+/// [`stop`](Self::stop), stops the reaper.
+///
+/// The reaper also stops by itself once the cache it sweeps is dropped.
+pub struct ReaperHandle {
+    pub(crate) worker: Option<Worker>,
+}
+
+pub(crate) enum Worker {
+    Thread {
+        shutdown: Sender<()>,
+        thread: JoinHandle<()>,
+    },
+    #[cfg(feature = "async")]
+    Task(tokio::task::JoinHandle<()>),
+}
+
+impl ReaperHandle {
+    /// Stop the reaper, waiting for a sweep in progress on a reaper thread
+    /// to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        match self.worker.take() {
+            Some(Worker::Thread { shutdown, thread }) => {
+                drop(shutdown);
+                // A removal listener run by the reaper may drop the handle;
+                // the thread then exits once the listener returns
+                if thread.thread().id() != thread::current().id() {
+                    let _ = thread.join();
+                }
+            }
+            #[cfg(feature = "async")]
+            Some(Worker::Task(task)) => task.abort(),
+            None => {}
+        }
+    }
+}
+
+impl Drop for ReaperHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Call `reap` on a new thread every `interval` until the handle is
+/// dropped or `reap` returns `false`.
+pub(crate) fn spawn_thread<F>(interval: Duration, mut reap: F) -> ReaperHandle
+where
+    F: FnMut() -> bool + Send + 'static,
+{
+    let interval = interval.max(MIN_INTERVAL);
+    let (shutdown, stopped) = mpsc::channel::<()>();
+    let thread = thread::Builder::new()
+        .name("memory_box-reaper".to_string())
+        .spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if !reap() {
+                    break;
+                }
+            }
+        })
+        .expect("failed to spawn reaper thread");
+    ReaperHandle {
+        worker: Some(Worker::Thread { shutdown, thread }),
+    }
+}
+
+/// Call `reap` from a task on the current Tokio runtime every `interval`
+/// until the handle is dropped or `reap` returns `false`.
+///
+/// `reap` takes a blocking lock and runs removal listeners, so it runs on
+/// the blocking thread pool rather than on a runtime worker.
+///
+/// # Panics
+/// Panics if called outside of a Tokio runtime.
+#[cfg(feature = "async")]
+pub(crate) fn spawn_task<F>(interval: Duration, mut reap: F) -> ReaperHandle
+where
+    F: FnMut() -> bool + Send + 'static,
+{
+    let interval = interval.max(MIN_INTERVAL);
+    let task = tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let sweep = tokio::task::spawn_blocking(move || {
+                let more = reap();
+                (reap, more)
+            });
+            match sweep.await {
+                Ok((returned, true)) => reap = returned,
+                _ => break,
+            }
+        }
+    });
+    ReaperHandle {
+        worker: Some(Worker::Task(task)),
+    }
+}
@@ -0,0 +1,455 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::bounded_cache::BoundedCache;
+use crate::listener::{RemovalCause, RemovalQueue};
+use crate::slab::{Link, List, Slab};
+
+/// Reads counted per entry saturate here.
+const MAX_FREQUENCY: u8 = 3;
+
+/// S3-FIFO cache.
+///
+/// New keys enter a small FIFO queue holding about 10% of the capacity.
+/// When one reaches the end of it, it moves to the main FIFO queue if it was
+/// read while in the small queue, and is evicted otherwise, leaving its key
+/// in a ghost queue. Most keys are never read again, so they leave quickly
+/// without disturbing the main queue; a key found in the ghost queue goes
+/// straight to the main queue. The main queue gives entries read since they
+/// last reached its end another pass instead of evicting them.
+///
+/// A hit only bumps a small counter and never moves the entry.
+pub struct S3FifoCache<K, V> {
+    map: HashMap<K, Slot>,
+    entries: Slab<Node<K, V>>,
+    entry_links: Vec<Link>,
+    ghosts: Slab<K>,
+    ghost_links: Vec<Link>,
+    small: List,
+    main: List,
+    ghost: List,
+    small_capacity: usize,
+    capacity: usize,
+    removals: RemovalQueue<K, V>,
+}
+
+/// Where a key lives, and its index in the matching slab.
+#[derive(Clone, Copy)]
+enum Slot {
+    Small(usize),
+    Main(usize),
+    Ghost(usize),
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    frequency: u8,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> S3FifoCache<K, V> {
+    /// Create a new cache holding at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            entries: Slab::new(),
+            entry_links: Vec::new(),
+            ghosts: Slab::new(),
+            ghost_links: Vec::new(),
+            small: List::new(),
+            main: List::new(),
+            ghost: List::new(),
+            small_capacity: (capacity / 10).max(1).min(capacity),
+            capacity,
+            removals: RemovalQueue::new(),
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.removals.set_listener(Arc::new(listener));
+        self
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.small.len() + self.main.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the key is cached, without counting it as a read.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        matches!(self.map.get(key), Some(Slot::Small(_) | Slot::Main(_)))
+    }
+
+    /// Get a reference to a value without counting it as a read.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match *self.map.get(key)? {
+            Slot::Small(idx) | Slot::Main(idx) => Some(&self.entries.get(idx).value),
+            Slot::Ghost(_) => None,
+        }
+    }
+
+    /// Get a value associated with a key.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (Slot::Small(idx) | Slot::Main(idx)) = *self.map.get(key)? else {
+            return None;
+        };
+        let node = self.entries.get_mut(idx);
+        node.frequency = (node.frequency + 1).min(MAX_FREQUENCY);
+        Some(node.value.clone())
+    }
+
+    /// Insert a key-value pair into the cache.
+    ///
+    /// Updating a cached key counts as a read of it.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let ghost = match self.map.get(&key).copied() {
+            Some(Slot::Small(idx) | Slot::Main(idx)) => {
+                let node = self.entries.get_mut(idx);
+                node.frequency = (node.frequency + 1).min(MAX_FREQUENCY);
+                let old_value = std::mem::replace(&mut node.value, value);
+                self.removals.record(key, old_value, RemovalCause::Replaced);
+                self.removals.notify();
+                return;
+            }
+            Some(Slot::Ghost(ghost)) => Some(ghost),
+            None => None,
+        };
+        if let Some(ghost) = ghost {
+            self.ghost.unlink(&mut self.ghost_links, ghost);
+            self.ghosts.remove(ghost);
+            self.map.remove(&key);
+        }
+        while self.len() >= self.capacity {
+            self.evict();
+        }
+
+        let idx = self.entries.insert(Node {
+            key: key.clone(),
+            value,
+            frequency: 0,
+        });
+        // Keys seen again soon after eviction skip the small queue
+        let slot = if ghost.is_some() && self.main_capacity() > 0 {
+            self.main.push_front(&mut self.entry_links, idx);
+            Slot::Main(idx)
+        } else {
+            self.small.push_front(&mut self.entry_links, idx);
+            Slot::Small(idx)
+        };
+        self.map.insert(key, slot);
+        self.removals.notify();
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = match *self.map.get(key)? {
+            Slot::Small(idx) => {
+                self.small.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::Main(idx) => {
+                self.main.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::Ghost(_) => return None,
+        };
+        let node = self.entries.remove(idx);
+        self.map.remove(key);
+        self.removals.record_cloned(node.key, &node.value, RemovalCause::Explicit);
+        self.removals.notify();
+        Some(node.value)
+    }
+
+    /// Remove every entry, reporting each as `RemovalCause::Cleared`, and
+    /// forget the ghost keys.
+    pub fn clear(&mut self) {
+        for list in [&mut self.small, &mut self.main] {
+            while let Some(idx) = list.back() {
+                list.unlink(&mut self.entry_links, idx);
+                let node = self.entries.remove(idx);
+                self.removals.record(node.key, node.value, RemovalCause::Cleared);
+            }
+        }
+        while let Some(idx) = self.ghost.back() {
+            self.ghost.unlink(&mut self.ghost_links, idx);
+            self.ghosts.remove(idx);
+        }
+        self.map.clear();
+        self.removals.notify();
+    }
+
+    fn main_capacity(&self) -> usize {
+        self.capacity - self.small_capacity
+    }
+
+    /// Evict exactly one entry, moving entries from the small queue to the
+    /// main one and giving main entries another pass along the way.
+    fn evict(&mut self) {
+        let main_capacity = self.main_capacity();
+        loop {
+            if self.small.len() >= self.small_capacity || self.main.is_empty() {
+                let idx = self.small.back().expect("a full cache has entries");
+                self.small.unlink(&mut self.entry_links, idx);
+                let node = self.entries.get_mut(idx);
+                if node.frequency > 0 && main_capacity > 0 {
+                    node.frequency = 0;
+                    self.main.push_front(&mut self.entry_links, idx);
+                    *self.map.get_mut(&node.key).expect("resident key is mapped") = Slot::Main(idx);
+                    if self.main.len() > main_capacity {
+                        self.evict_main();
+                        return;
+                    }
+                    continue;
+                }
+                let node = self.entries.remove(idx);
+                self.remember(node.key.clone());
+                self.removals.record(node.key, node.value, RemovalCause::Evicted);
+                return;
+            }
+            self.evict_main();
+            return;
+        }
+    }
+
+    /// Evict one entry from the main queue, moving entries read since their
+    /// last pass back to the front instead.
+    fn evict_main(&mut self) {
+        while let Some(idx) = self.main.back() {
+            let node = self.entries.get_mut(idx);
+            if node.frequency > 0 {
+                node.frequency -= 1;
+                self.main.move_to_front(&mut self.entry_links, idx);
+                continue;
+            }
+            self.main.unlink(&mut self.entry_links, idx);
+            let node = self.entries.remove(idx);
+            self.map.remove(&node.key);
+            self.removals.record(node.key, node.value, RemovalCause::Evicted);
+            return;
+        }
+    }
+
+    /// Put an evicted key in the ghost queue, which holds as many keys as
+    /// the main queue holds entries.
+    fn remember(&mut self, key: K) {
+        if self.main_capacity() == 0 {
+            self.map.remove(&key);
+            return;
+        }
+        if self.ghost.len() >= self.main_capacity() {
+            let oldest = self.ghost.back().expect("ghost queue is full");
+            self.ghost.unlink(&mut self.ghost_links, oldest);
+            let forgotten = self.ghosts.remove(oldest);
+            self.map.remove(&forgotten);
+        }
+        let ghost = self.ghosts.insert(key.clone());
+        self.ghost.push_front(&mut self.ghost_links, ghost);
+        *self.map.get_mut(&key).expect("evicted key is still mapped") = Slot::Ghost(ghost);
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> BoundedCache<K, V> for S3FifoCache<K, V> {
+    fn insert(&mut self, key: K, value: V) {
+        S3FifoCache::insert(self, key, value)
+    }
+
+    fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        S3FifoCache::get(self, key)
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        S3FifoCache::remove(self, key)
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        S3FifoCache::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        S3FifoCache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        S3FifoCache::capacity(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounded_cache::testing::touch;
+    use crate::lru_cache::LruCache;
+    use std::sync::Mutex;
+
+    fn in_main(cache: &S3FifoCache<u32, u32>, key: u32) -> bool {
+        matches!(cache.map.get(&key), Some(Slot::Main(_)))
+    }
+
+    fn in_ghost(cache: &S3FifoCache<u32, u32>, key: u32) -> bool {
+        matches!(cache.map.get(&key), Some(Slot::Ghost(_)))
+    }
+
+    #[test]
+    fn s3_fifo_cache_works() {
+        let mut cache = S3FifoCache::new(10);
+        for key in 0..10 {
+            cache.insert(key, key * 10);
+        }
+        assert_eq!(cache.len(), 10);
+        assert_eq!(cache.get(&3), Some(30));
+        assert_eq!(cache.remove(&3), Some(30));
+        assert!(!cache.contains_key(&3));
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_stores_nothing() {
+        let mut cache = S3FifoCache::new(0);
+        cache.insert(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn one_hit_wonders_leave_through_the_small_queue() {
+        // One small slot and nine main slots
+        let mut cache = S3FifoCache::new(10);
+        for hot in 0..5 {
+            touch(&mut cache, hot);
+            assert!(touch(&mut cache, hot));
+        }
+        // Once the cache is full, the hot keys reach the end of the small
+        // queue; they were read there, so they move to the main queue
+        for wonder in 100..1_000 {
+            touch(&mut cache, wonder);
+        }
+        for hot in 0..5 {
+            assert!(in_main(&cache, hot), "hot key {} was not promoted", hot);
+        }
+        // The one-hit wonders were never promoted, only remembered
+        assert!((100..1_000).all(|wonder| !in_main(&cache, wonder)));
+        assert!(cache.contains_key(&999));
+        assert!(in_ghost(&cache, 994));
+        assert_eq!(cache.ghost.len(), 9);
+
+        // LRU lets the same stream flush the hot keys
+        let mut lru = LruCache::new(10);
+        for hot in 0..5 {
+            touch(&mut lru, hot);
+            touch(&mut lru, hot);
+        }
+        for wonder in 100..1_000 {
+            touch(&mut lru, wonder);
+        }
+        assert!((0..5).all(|hot| !lru.contains_key(&hot)));
+    }
+
+    #[test]
+    fn ghost_hits_go_straight_to_main() {
+        // One small slot and one main slot
+        let mut cache = S3FifoCache::new(2);
+        for key in 1..=3 {
+            cache.insert(key, key);
+        }
+        assert!(in_ghost(&cache, 1));
+        assert!(!cache.contains_key(&1));
+
+        cache.insert(1, 1);
+        assert!(in_main(&cache, 1));
+        assert!(in_ghost(&cache, 2));
+    }
+
+    #[test]
+    fn main_queue_gives_read_entries_another_pass() {
+        // One small slot and three main slots
+        let mut cache = S3FifoCache::new(4);
+        for key in 1..=4 {
+            cache.insert(key, key);
+        }
+        for key in 1..=3 {
+            cache.get(&key);
+        }
+        // 4 was never read: it is evicted and 1, 2 and 3 move to main
+        cache.insert(5, 5);
+        assert!((1..=3).all(|key| in_main(&cache, key)));
+        assert!(in_ghost(&cache, 4));
+
+        cache.get(&1);
+        cache.get(&5);
+        // 5 moves to the full main queue; 1 was read since it got there, so 2 goes
+        cache.insert(6, 6);
+        assert!(in_main(&cache, 5));
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        assert!(!in_ghost(&cache, 2));
+    }
+
+    #[test]
+    fn removal_listener_reports_causes() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = S3FifoCache::new(1)
+            .with_removal_listener(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+
+        cache.insert(1, "one");
+        cache.insert(1, "uno");
+        cache.insert(2, "two");
+        cache.remove(&2);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (1, "one", RemovalCause::Replaced),
+                (1, "uno", RemovalCause::Evicted),
+                (2, "two", RemovalCause::Explicit),
+            ]
+        );
+    }
+}
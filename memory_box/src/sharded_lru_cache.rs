@@ -0,0 +1,277 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Mutex};
+
+use crate::listener::{self, RemovalCause, RemovalListener};
+use crate::lru_cache::LruCache;
+
+/// Concurrent LRU cache split into independently locked shards.
+///
+/// Each key is hashed to one shard, so callers working on different shards
+/// never wait on each other. Recency is tracked per shard: the entry evicted
+/// is the least recently used one in the shard receiving the insert, not
+/// necessarily in the whole cache.
+pub struct ShardedLruCache<K, V, S = RandomState> {
+    shards: Box<[Mutex<LruCache<K, V>>]>,
+    hasher: S,
+    listener: Option<RemovalListener<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ShardedLruCache<K, V> {
+    /// Create a sharded cache holding about `capacity` entries, using four
+    /// shards per available CPU.
+    pub fn new(capacity: usize) -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shards(capacity, cpus * 4)
+    }
+
+    /// Create a sharded cache with an explicit shard count.
+    ///
+    /// The capacity is divided evenly between shards, rounding up, and the
+    /// shard count is capped so that every shard can hold at least one entry.
+    pub fn with_shards(capacity: usize, shards: usize) -> Self {
+        Self::with_shards_and_hasher(capacity, shards, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher> ShardedLruCache<K, V, S> {
+    /// Create a sharded cache that picks shards with the given hasher.
+    pub fn with_shards_and_hasher(capacity: usize, shards: usize, hasher: S) -> Self {
+        let shards = shards.clamp(1, capacity.max(1));
+        let per_shard = capacity.div_ceil(shards);
+        Self {
+            shards: (0..shards)
+                .map(|_| Mutex::new(LruCache::new(per_shard)))
+                .collect(),
+            hasher,
+            listener: None,
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache. It runs after the shard lock is released, so it may
+    /// use the cache itself.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        let listener: RemovalListener<K, V> = Arc::new(listener);
+        for shard in self.shards.iter_mut() {
+            shard.get_mut().unwrap().set_removal_listener(Arc::clone(&listener));
+        }
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Number of shards the keys are spread across.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Insert a key-value pair into the cache.
+    pub fn insert(&self, key: K, value: V) {
+        let removals = {
+            let mut shard = self.shard(&key).lock().unwrap();
+            let _ = shard.insert_deferred(key, value);
+            shard.take_removals()
+        };
+        listener::notify(self.listener.as_ref(), removals);
+    }
+
+    /// Get a value associated with a key.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).lock().unwrap().get(key)
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, removals) = {
+            let mut shard = self.shard(key).lock().unwrap();
+            (shard.remove_deferred(key), shard.take_removals())
+        };
+        listener::notify(self.listener.as_ref(), removals);
+        value
+    }
+
+    /// Total capacity of the shards. Each shard's share is rounded up, so
+    /// this can exceed the capacity asked for.
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().capacity()).sum()
+    }
+
+    /// Change the total capacity, splitting it between the shards as at
+    /// construction. Returns the entries evicted to fit the new size.
+    ///
+    /// The shard count stays fixed, so any nonzero capacity still gives
+    /// every shard room for at least one entry: below the shard count, the
+    /// effective capacity is the shard count. [`capacity`](Self::capacity)
+    /// reports the result.
+    pub fn resize(&self, new_capacity: usize) -> Vec<(K, V)> {
+        let per_shard = new_capacity.div_ceil(self.shards.len());
+        let mut evicted = Vec::new();
+        for shard in self.shards.iter() {
+            let removals = {
+                let mut shard = shard.lock().unwrap();
+                evicted.extend(shard.resize_deferred(per_shard));
+                shard.take_removals()
+            };
+            listener::notify(self.listener.as_ref(), removals);
+        }
+        evicted
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &Mutex<LruCache<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        // The low bits also pick the bucket inside each shard's map, so use
+        // the high bits here to keep the two choices independent.
+        let idx = ((hash >> 32) as usize) % self.shards.len();
+        &self.shards[idx]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn sharded_cache_works() {
+        let cache = ShardedLruCache::with_shards(8, 4);
+        assert_eq!(cache.shard_count(), 4);
+
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert_eq!(cache.get(&"c"), None);
+
+        assert_eq!(cache.remove(&"a"), Some(1));
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn borrowed_key_lookups() {
+        let cache: ShardedLruCache<String, u32> = ShardedLruCache::with_shards(8, 4);
+        for (i, key) in ["a", "b", "c"].into_iter().enumerate() {
+            cache.insert(key.to_string(), i as u32);
+        }
+        assert_eq!(cache.get("b"), Some(1));
+        assert_eq!(cache.remove("c"), Some(2));
+        assert_eq!(cache.get("c"), None);
+    }
+
+    #[test]
+    fn shard_count_is_capped_by_capacity() {
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::with_shards(3, 16);
+        assert_eq!(cache.shard_count(), 3);
+
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::with_shards(0, 16);
+        assert_eq!(cache.shard_count(), 1);
+    }
+
+    #[test]
+    fn capacity_is_enforced_per_shard() {
+        let cache = ShardedLruCache::with_shards(1, 1);
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some("two"));
+    }
+
+    #[test]
+    fn resize_splits_capacity_between_shards() {
+        let cache = ShardedLruCache::with_shards(8, 2);
+        for i in 0..8 {
+            cache.insert(i, i);
+        }
+        // Keys may not split evenly, so some could already be evicted
+        let cached = (0..8).filter(|i| cache.get(i).is_some()).count();
+
+        let evicted = cache.resize(2);
+        let kept = (0..8).filter(|i| cache.get(i).is_some()).count();
+        assert!(kept <= 2);
+        assert_eq!(kept + evicted.len(), cached);
+    }
+
+    #[test]
+    fn resize_below_shard_count_keeps_one_entry_per_shard() {
+        let cache = ShardedLruCache::with_shards(16, 16);
+        for i in 0..64 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.capacity(), 16);
+
+        cache.resize(2);
+        assert_eq!(cache.capacity(), 16);
+        let kept = (0..64).filter(|i| cache.get(i).is_some()).count();
+        assert!(kept <= 16);
+
+        cache.resize(0);
+        assert_eq!(cache.capacity(), 0);
+        assert_eq!((0..64).filter(|i| cache.get(i).is_some()).count(), 0);
+    }
+
+    #[test]
+    fn listener_can_call_back_into_the_cache() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&evicted);
+        let cache = Arc::new_cyclic(|weak: &std::sync::Weak<ShardedLruCache<u32, u32>>| {
+            let weak = weak.clone();
+            ShardedLruCache::with_shards(1, 1).with_removal_listener(move |key, value, cause| {
+                // Would deadlock if the shard were still locked
+                let cache = weak.upgrade().unwrap();
+                assert_eq!(cache.get(&key), None);
+                sink.lock().unwrap().push((key, value, cause));
+            })
+        });
+
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        assert_eq!(cache.remove(&2), Some(20));
+        assert_eq!(
+            *evicted.lock().unwrap(),
+            vec![(1, 10, RemovalCause::Evicted), (2, 20, RemovalCause::Explicit)]
+        );
+    }
+
+    #[test]
+    fn shared_between_threads_without_outer_lock() {
+        assert_send_sync::<ShardedLruCache<String, Vec<u8>>>();
+
+        // Room for every key in each shard, however they are spread
+        let cache = Arc::new(ShardedLruCache::with_shards(8 * 800, 8));
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        cache.insert(t * 100 + i, i);
+                        assert_eq!(cache.get(&(t * 100 + i)), Some(i));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for key in 0..800 {
+            assert_eq!(cache.get(&key), Some(key % 100));
+        }
+    }
+}
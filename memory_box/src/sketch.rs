@@ -0,0 +1,187 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// Number of rows, i.e. independent counters consulted per item.
+const DEPTH: usize = 4;
+
+/// Counters saturate here; TinyLFU only needs to tell popular from unpopular.
+const MAX_COUNT: u8 = 15;
+
+/// Approximate per-item access counts in a fixed amount of memory.
+///
+/// Each item maps to one counter in each of several rows, and its estimate
+/// is the smallest of them, so it can only over-count, and only when items
+/// collide in every row. Once enough increments have been recorded all the
+/// counts are halved, so the sketch follows recent popularity rather than
+/// all-time totals.
+pub struct CountMinSketch {
+    counters: Vec<u8>,
+    width_mask: usize,
+    additions: usize,
+    sample_size: usize,
+    hasher: RandomState,
+}
+
+impl CountMinSketch {
+    /// A sketch sized for about `capacity` distinct items, halving its
+    /// counts after ten increments per item.
+    pub fn new(capacity: usize) -> Self {
+        let width = capacity.max(1).next_power_of_two();
+        Self {
+            counters: vec![0; width * DEPTH],
+            width_mask: width - 1,
+            additions: 0,
+            sample_size: capacity.max(1).saturating_mul(10),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Count one access to `item`. Returns `true` if this increment filled
+    /// the sample and every count was halved.
+    pub fn increment<T: Hash + ?Sized>(&mut self, item: &T) -> bool {
+        let hash = self.hasher.hash_one(item);
+        for idx in indices(self.width_mask, hash) {
+            if self.counters[idx] < MAX_COUNT {
+                self.counters[idx] += 1;
+            }
+        }
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.halve();
+            return true;
+        }
+        false
+    }
+
+    /// Estimated number of recent accesses to `item`, never lower than the
+    /// true count (up to the saturation point of 15).
+    pub fn estimate<T: Hash + ?Sized>(&self, item: &T) -> u8 {
+        let hash = self.hasher.hash_one(item);
+        indices(self.width_mask, hash)
+            .map(|idx| self.counters[idx])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Halve every count, so old accesses weigh half as much as new ones.
+    pub fn halve(&mut self) {
+        for counter in &mut self.counters {
+            *counter >>= 1;
+        }
+        self.additions /= 2;
+    }
+}
+
+/// The counter for `hash` in each row of a sketch, picked by double hashing.
+fn indices(width_mask: usize, hash: u64) -> impl Iterator<Item = usize> {
+    let (h1, h2) = (hash as u32 as usize, (hash >> 32) as usize | 1);
+    (0..DEPTH).map(move |row| {
+        let column = h1.wrapping_add(row.wrapping_mul(h2)) & width_mask;
+        row * (width_mask + 1) + column
+    })
+}
+
+/// Number of bits set per item.
+const PROBES: usize = 3;
+
+/// A Bloom filter that remembers which items were seen at least once.
+///
+/// Placed in front of a [`CountMinSketch`], it keeps the one-off items that
+/// make up most traffic out of the sketch: only an item seen a second time
+/// is counted there. It is cleared whenever the sketch halves its counts.
+pub struct Doorkeeper {
+    bits: Vec<u64>,
+    bit_mask: usize,
+    hasher: RandomState,
+}
+
+impl Doorkeeper {
+    /// A filter sized for about `capacity` distinct items.
+    pub fn new(capacity: usize) -> Self {
+        let bits = capacity.max(1).saturating_mul(8).next_power_of_two().max(64);
+        Self {
+            bits: vec![0; bits / 64],
+            bit_mask: bits - 1,
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Record `item`. Returns `true` if it was (probably) already present.
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) -> bool {
+        let hash = self.hasher.hash_one(item);
+        let mut present = true;
+        for bit in positions(self.bit_mask, hash) {
+            let (word, mask) = (bit / 64, 1 << (bit % 64));
+            present &= self.bits[word] & mask != 0;
+            self.bits[word] |= mask;
+        }
+        present
+    }
+
+    /// Returns `true` if `item` was (probably) inserted since the last clear.
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        let hash = self.hasher.hash_one(item);
+        positions(self.bit_mask, hash)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Forget every item.
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+}
+
+/// The bits for `hash` in a doorkeeper, picked by double hashing.
+fn positions(bit_mask: usize, hash: u64) -> impl Iterator<Item = usize> {
+    let (h1, h2) = (hash as u32 as usize, (hash >> 32) as usize | 1);
+    (0..PROBES).map(move |probe| h1.wrapping_add(probe.wrapping_mul(h2)) & bit_mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sketch_never_underestimates() {
+        let mut sketch = CountMinSketch::new(64);
+        for key in 0..64u32 {
+            for _ in 0..(key % 8) {
+                sketch.increment(&key);
+            }
+        }
+        for key in 0..64u32 {
+            assert!(sketch.estimate(&key) as u32 >= key % 8);
+        }
+    }
+
+    #[test]
+    fn sketch_saturates_and_halves() {
+        let mut sketch = CountMinSketch::new(16);
+        for _ in 0..20 {
+            sketch.increment("hot");
+        }
+        assert_eq!(sketch.estimate("hot"), MAX_COUNT);
+        sketch.halve();
+        assert_eq!(sketch.estimate("hot"), MAX_COUNT / 2);
+    }
+
+    #[test]
+    fn sketch_halves_after_a_full_sample() {
+        let mut sketch = CountMinSketch::new(1);
+        let halved: Vec<bool> = (0..10).map(|_| sketch.increment(&1)).collect();
+        assert_eq!(halved.iter().filter(|&&h| h).count(), 1);
+        assert!(halved[9]);
+        assert_eq!(sketch.estimate(&1), 5);
+    }
+
+    #[test]
+    fn doorkeeper_remembers_until_cleared() {
+        let mut doorkeeper = Doorkeeper::new(32);
+        assert!(!doorkeeper.contains("a"));
+        assert!(!doorkeeper.insert("a"));
+        assert!(doorkeeper.insert("a"));
+        assert!(doorkeeper.contains("a"));
+        doorkeeper.clear();
+        assert!(!doorkeeper.contains("a"));
+    }
+}
@@ -0,0 +1,234 @@
+//! Index-addressed node storage shared by the cache implementations.
+//!
+//! Nodes live in a single `Vec` and refer to each other by index, so list
+//! operations never lock or reference-count, and dropping the slab frees
+//! every node at once. List links are kept in a separate table indexed the
+//! same way, so one table can thread several lists through the same nodes.
+
+/// Sentinel index meaning "no node".
+pub(crate) const NIL: usize = usize::MAX;
+
+/// Previous/next indices of a node in an intrusive doubly linked list.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Link {
+    pub(crate) prev: usize,
+    pub(crate) next: usize,
+}
+
+impl Link {
+    const UNLINKED: Link = Link { prev: NIL, next: NIL };
+}
+
+/// Storage that can hand out the `Link` of a node by index.
+pub(crate) trait Links {
+    fn link(&self, idx: usize) -> Link;
+    fn link_mut(&mut self, idx: usize) -> &mut Link;
+}
+
+/// A table of links indexed by node, growing as new indices are linked.
+impl Links for Vec<Link> {
+    fn link(&self, idx: usize) -> Link {
+        self[idx]
+    }
+
+    fn link_mut(&mut self, idx: usize) -> &mut Link {
+        if idx >= self.len() {
+            self.resize(idx + 1, Link::UNLINKED);
+        }
+        &mut self[idx]
+    }
+}
+
+enum Slot<T> {
+    Occupied(T),
+    Vacant { next_free: usize },
+}
+
+/// A vector of nodes with a free list, so removed slots are reused.
+pub(crate) struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    free: usize,
+    len: usize,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: NIL,
+            len: 0,
+        }
+    }
+
+    /// Store a value and return the index it lives at.
+    pub(crate) fn insert(&mut self, value: T) -> usize {
+        let slot = Slot::Occupied(value);
+        self.len += 1;
+        if self.free == NIL {
+            self.slots.push(slot);
+            self.slots.len() - 1
+        } else {
+            let idx = self.free;
+            match std::mem::replace(&mut self.slots[idx], slot) {
+                Slot::Vacant { next_free } => self.free = next_free,
+                Slot::Occupied(_) => unreachable!("free list points at an occupied slot"),
+            }
+            idx
+        }
+    }
+
+    /// Remove the value at `idx`. The caller must have unlinked it first.
+    pub(crate) fn remove(&mut self, idx: usize) -> T {
+        let vacant = Slot::Vacant {
+            next_free: self.free,
+        };
+        match std::mem::replace(&mut self.slots[idx], vacant) {
+            Slot::Occupied(value) => {
+                self.free = idx;
+                self.len -= 1;
+                value
+            }
+            Slot::Vacant { .. } => panic!("slab index {} is vacant", idx),
+        }
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> &T {
+        match &self.slots[idx] {
+            Slot::Occupied(value) => value,
+            Slot::Vacant { .. } => panic!("slab index {} is vacant", idx),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, idx: usize) -> &mut T {
+        match &mut self.slots[idx] {
+            Slot::Occupied(value) => value,
+            Slot::Vacant { .. } => panic!("slab index {} is vacant", idx),
+        }
+    }
+}
+
+/// Head and tail of a doubly linked list threaded through some `Links` storage.
+///
+/// The front is the most recently pushed end; several lists can share one
+/// link table as long as each node is on at most one of them.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct List {
+    head: usize,
+    tail: usize,
+    len: usize,
+}
+
+impl Default for List {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl List {
+    pub(crate) const fn new() -> Self {
+        Self {
+            head: NIL,
+            tail: NIL,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head == NIL
+    }
+
+    pub(crate) fn front(&self) -> Option<usize> {
+        (self.head != NIL).then_some(self.head)
+    }
+
+    pub(crate) fn back(&self) -> Option<usize> {
+        (self.tail != NIL).then_some(self.tail)
+    }
+
+    /// The node after `idx`, walking from front to back.
+    pub(crate) fn next<L: Links + ?Sized>(&self, links: &L, idx: usize) -> Option<usize> {
+        let next = links.link(idx).next;
+        (next != NIL).then_some(next)
+    }
+
+    /// The node before `idx`, walking from back to front.
+    pub(crate) fn prev<L: Links + ?Sized>(&self, links: &L, idx: usize) -> Option<usize> {
+        let prev = links.link(idx).prev;
+        (prev != NIL).then_some(prev)
+    }
+
+    pub(crate) fn push_front<L: Links + ?Sized>(&mut self, links: &mut L, idx: usize) {
+        *links.link_mut(idx) = Link {
+            prev: NIL,
+            next: self.head,
+        };
+        if self.head == NIL {
+            self.tail = idx;
+        } else {
+            links.link_mut(self.head).prev = idx;
+        }
+        self.head = idx;
+        self.len += 1;
+    }
+
+    pub(crate) fn push_back<L: Links + ?Sized>(&mut self, links: &mut L, idx: usize) {
+        *links.link_mut(idx) = Link {
+            prev: self.tail,
+            next: NIL,
+        };
+        if self.tail == NIL {
+            self.head = idx;
+        } else {
+            links.link_mut(self.tail).next = idx;
+        }
+        self.tail = idx;
+        self.len += 1;
+    }
+
+    /// Link `idx` just in front of `at`, which must be on this list.
+    pub(crate) fn insert_before<L: Links + ?Sized>(&mut self, links: &mut L, at: usize, idx: usize) {
+        let prev = links.link(at).prev;
+        *links.link_mut(idx) = Link { prev, next: at };
+        links.link_mut(at).prev = idx;
+        if prev == NIL {
+            self.head = idx;
+        } else {
+            links.link_mut(prev).next = idx;
+        }
+        self.len += 1;
+    }
+
+    /// Detach `idx` from this list. It must currently be on it.
+    pub(crate) fn unlink<L: Links + ?Sized>(&mut self, links: &mut L, idx: usize) {
+        let Link { prev, next } = links.link(idx);
+        if prev == NIL {
+            self.head = next;
+        } else {
+            links.link_mut(prev).next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            links.link_mut(next).prev = prev;
+        }
+        *links.link_mut(idx) = Link::UNLINKED;
+        self.len -= 1;
+    }
+
+    pub(crate) fn move_to_front<L: Links + ?Sized>(&mut self, links: &mut L, idx: usize) {
+        if self.head != idx {
+            self.unlink(links, idx);
+            self.push_front(links, idx);
+        }
+    }
+}
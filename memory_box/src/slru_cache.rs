@@ -0,0 +1,196 @@
+use crate::cache::Cache;
+use crate::policy::EvictionPolicy;
+use crate::slab::{Link, List};
+
+/// Segmented LRU Cache Implementation
+///
+/// A [`Cache`] split into a probationary and a protected segment. New keys
+/// start on probation and only a second use promotes them, so a burst of
+/// keys seen once cannot push out the entries that are used repeatedly.
+///
+/// Use [`Slru::with_protected_percent`] through [`Cache::with_policy`] to
+/// change the split.
+pub type SlruCache<K, V> = Cache<K, V, Slru>;
+
+/// Evicts the least recently used entry on probation, and only falls back
+/// to the protected segment when probation is empty.
+///
+/// Entries used a second time are promoted to the protected segment. When
+/// it outgrows its share of the entries, its least recently used entry is
+/// demoted back to the front of probation. Iterates from the most recently
+/// used protected entry to the least recently used one on probation.
+pub struct Slru {
+    links: Vec<Link>,
+    probation: List,
+    protected: List,
+    /// Whether each slot is in the protected segment, indexed by slot.
+    is_protected: Vec<bool>,
+    protected_percent: usize,
+}
+
+impl Default for Slru {
+    /// A split giving 80% of the entries to the protected segment.
+    fn default() -> Self {
+        Self::with_protected_percent(80)
+    }
+}
+
+impl Slru {
+    /// A split letting the protected segment hold up to `percent` of the
+    /// entries, capped at 100.
+    pub fn with_protected_percent(percent: usize) -> Self {
+        Self {
+            links: Vec::new(),
+            probation: List::new(),
+            protected: List::new(),
+            is_protected: Vec::new(),
+            protected_percent: percent.min(100),
+        }
+    }
+
+    /// Most entries the protected segment may hold, rounded up so a
+    /// nearly empty cache can still protect something.
+    fn protected_limit(&self) -> usize {
+        ((self.probation.len() + self.protected.len()) * self.protected_percent).div_ceil(100)
+    }
+}
+
+impl EvictionPolicy for Slru {
+    fn on_insert(&mut self, slot: usize) {
+        if slot >= self.is_protected.len() {
+            self.is_protected.resize(slot + 1, false);
+        }
+        self.is_protected[slot] = false;
+        self.probation.push_front(&mut self.links, slot);
+    }
+
+    fn on_access(&mut self, slot: usize) {
+        if self.is_protected[slot] {
+            self.protected.move_to_front(&mut self.links, slot);
+            return;
+        }
+        self.probation.unlink(&mut self.links, slot);
+        self.protected.push_front(&mut self.links, slot);
+        self.is_protected[slot] = true;
+        if self.protected.len() > self.protected_limit() {
+            let demoted = self.protected.back().expect("protected segment is over its limit");
+            self.protected.unlink(&mut self.links, demoted);
+            self.probation.push_front(&mut self.links, demoted);
+            self.is_protected[demoted] = false;
+        }
+    }
+
+    fn on_remove(&mut self, slot: usize) {
+        if self.is_protected[slot] {
+            self.protected.unlink(&mut self.links, slot);
+        } else {
+            self.probation.unlink(&mut self.links, slot);
+        }
+    }
+
+    fn victim(&self) -> Option<usize> {
+        self.probation.back().or(self.protected.back())
+    }
+
+    fn first(&self) -> Option<usize> {
+        self.protected.front().or(self.probation.front())
+    }
+
+    fn next(&self, slot: usize) -> Option<usize> {
+        if self.is_protected[slot] {
+            self.protected
+                .next(&self.links, slot)
+                .or(self.probation.front())
+        } else {
+            self.probation.next(&self.links, slot)
+        }
+    }
+
+    fn last(&self) -> Option<usize> {
+        self.victim()
+    }
+
+    fn prev(&self, slot: usize) -> Option<usize> {
+        if self.is_protected[slot] {
+            self.protected.prev(&self.links, slot)
+        } else {
+            self.probation
+                .prev(&self.links, slot)
+                .or(self.protected.back())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(cache: &SlruCache<u32, u32>) -> Vec<u32> {
+        cache.keys().copied().collect()
+    }
+
+    #[test]
+    fn keys_seen_once_do_not_displace_protected_ones() {
+        let mut cache = SlruCache::new(4);
+        for key in [1, 2] {
+            cache.insert(key, key);
+            cache.get(&key);
+        }
+        for key in 10..20 {
+            cache.insert(key, key);
+        }
+        assert!(cache.contains_key(&1));
+        assert!(cache.contains_key(&2));
+        assert_eq!(keys(&cache), vec![2, 1, 19, 18]);
+    }
+
+    #[test]
+    fn protected_overflow_is_demoted_to_probation() {
+        let mut cache = Cache::with_policy(4, Slru::with_protected_percent(50));
+        for key in 1..=4 {
+            cache.insert(key, key);
+        }
+        for key in 1..=3 {
+            cache.get(&key);
+        }
+        // Only two entries fit in the protected half, so 1 went back on probation
+        assert_eq!(keys(&cache), vec![3, 2, 1, 4]);
+        let backward: Vec<u32> = cache.keys().rev().copied().collect();
+        assert_eq!(backward, vec![4, 1, 2, 3]);
+
+        cache.insert(5, 5);
+        assert!(!cache.contains_key(&4));
+        cache.insert(6, 6);
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&2));
+    }
+
+    #[test]
+    fn empty_probation_falls_back_to_protected() {
+        let mut cache = Cache::with_policy(2, Slru::with_protected_percent(100));
+        for key in 1..=2 {
+            cache.insert(key, key);
+            cache.get(&key);
+        }
+        cache.insert(3, 3);
+        assert_eq!(keys(&cache), vec![2, 3]);
+    }
+
+    #[test]
+    fn growing_a_protected_entry_keeps_it_protected() {
+        let mut cache = Cache::with_policy_and_weigher(10, Slru::default(), |_: &u32, v: &Vec<u8>| v.len());
+        cache.insert(1, vec![0; 2]);
+        cache.get(&1);
+        cache.insert(2, vec![0; 2]);
+        cache.insert(3, vec![0; 2]);
+
+        cache.insert(1, vec![0; 8]);
+        assert!(!cache.contains_key(&2));
+        // Keys seen once only displace each other on probation
+        for key in 10..13 {
+            cache.insert(key, vec![0; 2]);
+        }
+        assert_eq!(cache.peek(&1).map(Vec::len), Some(8));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 12]);
+    }
+}
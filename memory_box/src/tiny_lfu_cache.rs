@@ -0,0 +1,393 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::bounded_cache::BoundedCache;
+use crate::listener::{RemovalCause, RemovalQueue};
+use crate::sketch::{CountMinSketch, Doorkeeper};
+use crate::slab::{Link, List, Slab};
+
+/// W-TinyLFU cache.
+///
+/// New entries land in a small LRU window, about 1% of the capacity. Entries
+/// pushed out of the window must win an admission contest to enter the main
+/// area: they only replace the main area's next victim if they have been
+/// used more often recently, as estimated by a [`CountMinSketch`] behind a
+/// [`Doorkeeper`]. The main area is a segmented LRU, where entries used
+/// again move from a probation segment to a protected one.
+pub struct TinyLfuCache<K, V> {
+    map: HashMap<K, usize>,
+    entries: Slab<Node<K, V>>,
+    links: Vec<Link>,
+    window: List,
+    probation: List,
+    protected: List,
+    window_capacity: usize,
+    protected_capacity: usize,
+    capacity: usize,
+    sketch: CountMinSketch,
+    doorkeeper: Doorkeeper,
+    removals: RemovalQueue<K, V>,
+}
+
+/// Which list an entry is on.
+#[derive(Clone, Copy, PartialEq)]
+enum Segment {
+    Window,
+    Probation,
+    Protected,
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    segment: Segment,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TinyLfuCache<K, V> {
+    /// Create a new cache holding at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        let window_capacity = (capacity / 100).max(1).min(capacity);
+        let main_capacity = capacity - window_capacity;
+        // Very small sketches collide and halve too often to rank anything
+        let tracked = capacity.max(64);
+        Self {
+            map: HashMap::new(),
+            entries: Slab::new(),
+            links: Vec::new(),
+            window: List::new(),
+            probation: List::new(),
+            protected: List::new(),
+            window_capacity,
+            protected_capacity: main_capacity * 4 / 5,
+            capacity,
+            sketch: CountMinSketch::new(tracked),
+            doorkeeper: Doorkeeper::new(tracked),
+            removals: RemovalQueue::new(),
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache. Entries refused by the admission filter are
+    /// reported as `RemovalCause::Evicted`.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.removals.set_listener(Arc::new(listener));
+        self
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns `true` if the key is cached, without counting it as a use.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Get a reference to a value without counting it as a use.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        Some(&self.entries.get(idx).value)
+    }
+
+    /// Get a value associated with a key.
+    ///
+    /// Misses are counted too, so a key that is looked up often becomes
+    /// easier to admit once it is inserted.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.record_access(key);
+        let idx = *self.map.get(key)?;
+        self.touch(idx);
+        Some(self.entries.get(idx).value.clone())
+    }
+
+    /// Insert a key-value pair into the cache.
+    ///
+    /// A new key always enters the window; whichever entry that pushes out
+    /// of the window may then be refused by the admission filter.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.record_access(&key);
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(&idx) = self.map.get(&key) {
+            self.touch(idx);
+            let old_value = std::mem::replace(&mut self.entries.get_mut(idx).value, value);
+            self.removals.record(key, old_value, RemovalCause::Replaced);
+            self.removals.notify();
+            return;
+        }
+
+        let idx = self.entries.insert(Node {
+            key: key.clone(),
+            value,
+            segment: Segment::Window,
+        });
+        self.window.push_front(&mut self.links, idx);
+        self.map.insert(key, idx);
+        if self.window.len() > self.window_capacity {
+            let candidate = self.window.back().expect("window is over capacity");
+            self.window.unlink(&mut self.links, candidate);
+            self.admit(candidate);
+        }
+        self.removals.notify();
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        let node = self.unlink(idx);
+        self.removals.record_cloned(node.key, &node.value, RemovalCause::Explicit);
+        self.removals.notify();
+        Some(node.value)
+    }
+
+    /// Remove every entry, reporting each as `RemovalCause::Cleared`. The
+    /// access counts are kept.
+    pub fn clear(&mut self) {
+        let slots: Vec<usize> = self.map.values().copied().collect();
+        for idx in slots {
+            let node = self.unlink(idx);
+            self.removals.record(node.key, node.value, RemovalCause::Cleared);
+        }
+        self.removals.notify();
+    }
+
+    /// Count an access in the sketch, letting first sightings only reach the
+    /// doorkeeper.
+    fn record_access<Q: Hash + ?Sized>(&mut self, key: &Q) {
+        if self.doorkeeper.insert(key) && self.sketch.increment(key) {
+            self.doorkeeper.clear();
+        }
+    }
+
+    /// Estimated recent accesses of a key.
+    fn frequency<Q: Hash + ?Sized>(&self, key: &Q) -> u8 {
+        self.sketch.estimate(key) + u8::from(self.doorkeeper.contains(key))
+    }
+
+    /// Mark the entry at `idx` as used, promoting it out of probation.
+    fn touch(&mut self, idx: usize) {
+        match self.entries.get(idx).segment {
+            Segment::Window => self.window.move_to_front(&mut self.links, idx),
+            Segment::Protected => self.protected.move_to_front(&mut self.links, idx),
+            Segment::Probation => {
+                self.probation.unlink(&mut self.links, idx);
+                self.protected.push_front(&mut self.links, idx);
+                self.entries.get_mut(idx).segment = Segment::Protected;
+                if self.protected.len() > self.protected_capacity {
+                    let demoted = self.protected.back().expect("protected is over capacity");
+                    self.protected.unlink(&mut self.links, demoted);
+                    self.probation.push_front(&mut self.links, demoted);
+                    self.entries.get_mut(demoted).segment = Segment::Probation;
+                }
+            }
+        }
+    }
+
+    /// Move `candidate`, just pushed out of the window, into probation if
+    /// there is room or if it is used more often than the main area's victim.
+    fn admit(&mut self, candidate: usize) {
+        let main_len = self.probation.len() + self.protected.len();
+        if main_len >= self.capacity - self.window_capacity {
+            let victim = self.probation.back().or(self.protected.back());
+            match victim {
+                Some(victim)
+                    if self.frequency(&self.entries.get(candidate).key)
+                        > self.frequency(&self.entries.get(victim).key) =>
+                {
+                    let node = self.unlink(victim);
+                    self.removals.record(node.key, node.value, RemovalCause::Evicted);
+                }
+                _ => {
+                    // The candidate is on no list any more
+                    let node = self.entries.remove(candidate);
+                    self.map.remove(&node.key);
+                    self.removals.record(node.key, node.value, RemovalCause::Evicted);
+                    return;
+                }
+            }
+        }
+        self.probation.push_front(&mut self.links, candidate);
+        self.entries.get_mut(candidate).segment = Segment::Probation;
+    }
+
+    /// Take the node at `idx` out of the map, its list and the slab.
+    fn unlink(&mut self, idx: usize) -> Node<K, V> {
+        let list = match self.entries.get(idx).segment {
+            Segment::Window => &mut self.window,
+            Segment::Probation => &mut self.probation,
+            Segment::Protected => &mut self.protected,
+        };
+        list.unlink(&mut self.links, idx);
+        let node = self.entries.remove(idx);
+        self.map.remove(&node.key);
+        node
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> BoundedCache<K, V> for TinyLfuCache<K, V> {
+    fn insert(&mut self, key: K, value: V) {
+        TinyLfuCache::insert(self, key, value)
+    }
+
+    fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        TinyLfuCache::get(self, key)
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        TinyLfuCache::remove(self, key)
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        TinyLfuCache::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        TinyLfuCache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        TinyLfuCache::capacity(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounded_cache::testing::touch;
+    use crate::lru_cache::LruCache;
+    use std::sync::Mutex;
+
+    #[test]
+    fn tiny_lfu_cache_works() {
+        let mut cache = TinyLfuCache::new(10);
+        for key in 0..10 {
+            cache.insert(key, key * 10);
+        }
+        assert_eq!(cache.len(), 10);
+        assert_eq!(cache.get(&3), Some(30));
+        assert_eq!(cache.remove(&3), Some(30));
+        assert!(!cache.contains_key(&3));
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_stores_nothing() {
+        let mut cache = TinyLfuCache::new(0);
+        cache.insert(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn only_more_frequent_keys_displace_the_victim() {
+        // One window slot and one main slot
+        let mut cache = TinyLfuCache::new(2);
+        cache.insert("hot", 0);
+        for _ in 0..5 {
+            cache.get("hot");
+        }
+        cache.insert("a", 1);
+        assert!(cache.contains_key("hot"));
+
+        // "a" leaves the window but is used less than "hot", so it is refused
+        cache.insert("b", 2);
+        assert!(cache.contains_key("hot"));
+        assert!(!cache.contains_key("a"));
+        cache.remove("b");
+
+        // A key asked for many times beats "hot" once it leaves the window.
+        // The sketch is seeded at random, so skip keys that happen to share
+        // every counter with "hot"
+        let popular = ["popular", "common", "frequent", "favourite"]
+            .into_iter()
+            .find(|key| cache.frequency(key) == 0)
+            .expect("not every key collides");
+        for _ in 0..10 {
+            cache.get(popular);
+        }
+        cache.insert(popular, 3);
+        cache.insert("c", 4);
+        assert!(cache.contains_key(popular));
+        assert!(!cache.contains_key("hot"));
+    }
+
+    #[test]
+    fn hit_rate_beats_lru_on_a_skewed_workload() {
+        // Hot keys 0..20 are requested over and over, mixed with a stream
+        // of keys that are never requested again
+        let workload: Vec<u32> = (0..20_000u32)
+            .map(|i| if i % 2 == 0 { (i / 2) % 20 } else { 1_000 + i })
+            .collect();
+        let hits = |cache: &mut dyn FnMut(u32) -> bool| workload.iter().filter(|&&k| cache(k)).count();
+
+        let mut lru = LruCache::new(20);
+        let mut tiny = TinyLfuCache::new(20);
+        let lru_hits = hits(&mut |key| touch(&mut lru, key));
+        let tiny_hits = hits(&mut |key| touch(&mut tiny, key));
+        assert!(tiny_hits > lru_hits * 2, "tiny {} vs lru {}", tiny_hits, lru_hits);
+    }
+
+    #[test]
+    fn refused_candidates_are_reported_as_evicted() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = TinyLfuCache::new(2)
+            .with_removal_listener(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+
+        cache.insert(1, "one");
+        cache.get(&1);
+        cache.get(&1);
+        cache.insert(2, "two");
+        cache.insert(2, "deux");
+        cache.insert(3, "three");
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![(2, "two", RemovalCause::Replaced), (2, "deux", RemovalCause::Evicted)]
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::TtlError;
+use crate::listener::{RemovalCause, RemovalQueue};
+use crate::slab::{Link, List, Slab};
+
+/// LRU cache whose entries also expire a given time after they are inserted.
+///
+/// Holds at most `capacity` entries. When it is full, expired entries are
+/// removed first, and only if none have expired is the least recently used
+/// entry evicted. Time is read from the clock `C`, the system clock by
+/// default.
+pub struct TtlLruCache<K, V, C = SystemClock> {
+    map: HashMap<K, usize>,
+    entries: Slab<Node<K, V>>,
+    links: Vec<Link>,
+    /// Most recently used at the front.
+    recency: List,
+    /// Entries ordered by expiry; the slot index breaks ties.
+    expiries: BTreeMap<(Instant, usize), ()>,
+    capacity: usize,
+    default_ttl: Option<Duration>,
+    clock: C,
+    removals: RemovalQueue<K, V>,
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    expiry: Instant,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TtlLruCache<K, V> {
+    /// Create a new cache holding at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_clock(capacity, SystemClock)
+    }
+
+    /// Create a new cache holding at most `capacity` entries, which live
+    /// for `ttl` unless inserted with a TTL of their own.
+    pub fn with_default_ttl(capacity: usize, ttl: Duration) -> Self {
+        Self::with_default_ttl_and_clock(capacity, ttl, SystemClock)
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, C: Clock> TtlLruCache<K, V, C> {
+    /// Create a new cache holding at most `capacity` entries, reading the
+    /// time from `clock`.
+    pub fn with_clock(capacity: usize, clock: C) -> Self {
+        Self {
+            map: HashMap::new(),
+            entries: Slab::new(),
+            links: Vec::new(),
+            recency: List::new(),
+            expiries: BTreeMap::new(),
+            capacity,
+            default_ttl: None,
+            clock,
+            removals: RemovalQueue::new(),
+        }
+    }
+
+    /// Create a new cache with a default TTL, reading the time from `clock`.
+    pub fn with_default_ttl_and_clock(capacity: usize, ttl: Duration, clock: C) -> Self {
+        Self {
+            default_ttl: Some(ttl),
+            ..Self::with_clock(capacity, clock)
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.removals.set_listener(Arc::new(listener));
+        self
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The TTL used by `insert_default`, if one was set.
+    pub fn default_ttl(&self) -> Option<Duration> {
+        self.default_ttl
+    }
+
+    /// Number of entries in the cache, including expired ones that have
+    /// not been removed yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns `true` if the key is cached and has not expired, without
+    /// marking it as used.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.peek(key).is_some()
+    }
+
+    /// Get a reference to a live value without marking it as used.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.entries.get(*self.map.get(key)?);
+        (node.expiry > self.clock.now()).then_some(&node.value)
+    }
+
+    /// Get the value associated with a key and mark it as most recently
+    /// used. An expired value is removed instead.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        if self.entries.get(idx).expiry <= self.clock.now() {
+            self.remove_slot(idx, RemovalCause::Expired);
+            self.removals.notify();
+            return None;
+        }
+        self.recency.move_to_front(&mut self.links, idx);
+        Some(self.entries.get(idx).value.clone())
+    }
+
+    /// Insert a key-value pair that lives for `ttl`. If the cache is full,
+    /// expired entries are removed first, then the least recently used one.
+    ///
+    /// Fails without changing the cache if `ttl` is too long to represent.
+    pub fn insert(&mut self, key: K, value: V, ttl: Duration) -> Result<(), TtlError> {
+        let now = self.clock.now();
+        let expiry = now.checked_add(ttl).ok_or(TtlError::TooLong(ttl))?;
+        if self.capacity == 0 {
+            return Ok(());
+        }
+
+        if let Some(&idx) = self.map.get(&key) {
+            let node = self.entries.get_mut(idx);
+            let old_expiry = std::mem::replace(&mut node.expiry, expiry);
+            let old_value = std::mem::replace(&mut node.value, value);
+            self.expiries.remove(&(old_expiry, idx));
+            self.expiries.insert((expiry, idx), ());
+            self.recency.move_to_front(&mut self.links, idx);
+            let cause = if old_expiry > now {
+                RemovalCause::Replaced
+            } else {
+                RemovalCause::Expired
+            };
+            self.removals.record(key, old_value, cause);
+        } else {
+            if self.map.len() >= self.capacity {
+                self.remove_expired_at(now);
+            }
+            while self.map.len() >= self.capacity {
+                self.evict_lru();
+            }
+            let idx = self.entries.insert(Node {
+                key: key.clone(),
+                value,
+                expiry,
+            });
+            self.recency.push_front(&mut self.links, idx);
+            self.expiries.insert((expiry, idx), ());
+            self.map.insert(key, idx);
+        }
+        self.removals.notify();
+        Ok(())
+    }
+
+    /// Insert a key-value pair that lives for the cache's default TTL.
+    ///
+    /// Fails with `TtlError::NoDefault` if the cache has no default TTL.
+    pub fn insert_default(&mut self, key: K, value: V) -> Result<(), TtlError> {
+        let ttl = self.default_ttl.ok_or(TtlError::NoDefault)?;
+        self.insert(key, value, ttl)
+    }
+
+    /// Remove a key-value pair from the cache, returning the value if it
+    /// had not expired.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        let node = self.take(idx);
+        if node.expiry <= self.clock.now() {
+            self.removals.record(node.key, node.value, RemovalCause::Expired);
+            self.removals.notify();
+            return None;
+        }
+        self.removals.record_cloned(node.key, &node.value, RemovalCause::Explicit);
+        self.removals.notify();
+        Some(node.value)
+    }
+
+    /// Remove every expired entry, reporting each as `RemovalCause::Expired`.
+    pub fn remove_expired(&mut self) {
+        self.remove_expired_at(self.clock.now());
+        self.removals.notify();
+    }
+
+    /// Remove every entry, reporting each as `RemovalCause::Cleared`.
+    pub fn clear(&mut self) {
+        while let Some(idx) = self.recency.back() {
+            self.remove_slot(idx, RemovalCause::Cleared);
+        }
+        self.removals.notify();
+    }
+
+    /// Change the capacity. When shrinking, expired entries are removed
+    /// first, then the least recently used ones until the rest fit. Entries
+    /// evicted that way are reported to the removal listener as
+    /// `RemovalCause::Evicted` and also returned, in eviction order.
+    pub fn resize(&mut self, new_capacity: usize) -> Vec<(K, V)> {
+        self.capacity = new_capacity;
+        self.removals.collect_evictions();
+        if self.map.len() > self.capacity {
+            self.remove_expired_at(self.clock.now());
+        }
+        while self.map.len() > self.capacity {
+            self.evict_lru();
+        }
+        let evicted = self.removals.take_evictions();
+        self.removals.notify();
+        evicted
+    }
+
+    fn remove_expired_at(&mut self, now: Instant) {
+        while let Some((&(expiry, idx), _)) = self.expiries.first_key_value() {
+            if expiry > now {
+                break;
+            }
+            self.remove_slot(idx, RemovalCause::Expired);
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        if let Some(idx) = self.recency.back() {
+            self.remove_slot(idx, RemovalCause::Evicted);
+        }
+    }
+
+    /// Drop the entry in `idx`, recording why.
+    fn remove_slot(&mut self, idx: usize, cause: RemovalCause) {
+        let node = self.take(idx);
+        self.removals.record(node.key, node.value, cause);
+    }
+
+    /// Unlink the entry in `idx` from every structure and return it.
+    fn take(&mut self, idx: usize) -> Node<K, V> {
+        self.recency.unlink(&mut self.links, idx);
+        let node = self.entries.remove(idx);
+        self.expiries.remove(&(node.expiry, idx));
+        self.map.remove(&node.key);
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::Mutex;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn ttl_lru_cache_works() {
+        let mut cache = TtlLruCache::with_default_ttl(2, MINUTE);
+        cache.insert_default("a", 1).unwrap();
+        cache.insert("b", 2, MINUTE).unwrap();
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.peek("b"), Some(&2));
+        assert_eq!(cache.remove("b"), Some(2));
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn capacity_bounds_distinct_keys() {
+        let mut cache = TtlLruCache::new(3);
+        for key in 0..1_000 {
+            cache.insert(key, key, MINUTE).unwrap();
+        }
+        assert_eq!(cache.len(), 3);
+        assert!((997..1_000).all(|key| cache.contains_key(&key)));
+    }
+
+    #[test]
+    fn entries_expire() {
+        let clock = MockClock::new();
+        let mut cache = TtlLruCache::with_clock(4, clock.clone());
+        cache.insert("short", 1, Duration::from_secs(1)).unwrap();
+        cache.insert("long", 2, MINUTE).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert!(!cache.contains_key("short"));
+        assert_eq!(cache.get("short"), None);
+        assert_eq!(cache.get("long"), Some(2));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn expired_entries_are_evicted_before_lru_ones() {
+        let clock = MockClock::new();
+        let mut cache = TtlLruCache::with_clock(3, clock.clone());
+        cache.insert("old", 1, MINUTE).unwrap();
+        cache.insert("fresh", 2, Duration::from_secs(1)).unwrap();
+        cache.insert("other", 3, MINUTE).unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        // "old" is least recently used, but "fresh" has expired
+        cache.insert("new", 4, MINUTE).unwrap();
+        assert!(cache.contains_key("old"));
+        assert!(!cache.contains_key("fresh"));
+
+        cache.get("old");
+        cache.insert("newer", 5, MINUTE).unwrap();
+        assert!(!cache.contains_key("other"));
+        assert!(cache.contains_key("old"));
+    }
+
+    #[test]
+    fn removal_listener_reports_causes() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let clock = MockClock::new();
+        let mut cache = TtlLruCache::with_clock(1, clock.clone())
+            .with_removal_listener(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+
+        cache.insert(1, "one", MINUTE).unwrap();
+        cache.insert(1, "uno", Duration::from_secs(1)).unwrap();
+        clock.advance(Duration::from_secs(1));
+        cache.insert(2, "two", MINUTE).unwrap();
+        cache.insert(3, "three", MINUTE).unwrap();
+        cache.remove(&3);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (1, "one", RemovalCause::Replaced),
+                (1, "uno", RemovalCause::Expired),
+                (2, "two", RemovalCause::Evicted),
+                (3, "three", RemovalCause::Explicit),
+            ]
+        );
+    }
+
+    #[test]
+    fn resize_returns_only_evicted_entries() {
+        let clock = MockClock::new();
+        let mut cache = TtlLruCache::with_clock(4, clock.clone());
+        cache.insert(0, 0, Duration::from_secs(1)).unwrap();
+        for key in 1..4 {
+            cache.insert(key, key, MINUTE).unwrap();
+        }
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.resize(2), vec![(1, 1)]);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn bad_ttls_are_errors() {
+        let mut cache = TtlLruCache::new(2);
+        assert_eq!(cache.insert(1, 1, Duration::MAX), Err(TtlError::TooLong(Duration::MAX)));
+        assert_eq!(cache.insert_default(1, 1), Err(TtlError::NoDefault));
+        assert!(cache.is_empty());
+    }
+}
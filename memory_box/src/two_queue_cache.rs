@@ -0,0 +1,414 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::bounded_cache::BoundedCache;
+use crate::listener::{RemovalCause, RemovalQueue};
+use crate::slab::{Link, List, Slab};
+
+/// 2Q cache.
+///
+/// New keys enter A1in, a FIFO queue holding about a quarter of the
+/// capacity, where hits do not reorder them. Keys pushed out of A1in are
+/// remembered in A1out, a ghost queue of keys only. A key inserted again
+/// while still in A1out has proven itself and goes to Am, an LRU holding
+/// the rest of the capacity. Keys seen only once never reach Am, so scans
+/// cannot flush it.
+pub struct TwoQueueCache<K, V> {
+    map: HashMap<K, Slot>,
+    entries: Slab<Node<K, V>>,
+    entry_links: Vec<Link>,
+    ghosts: Slab<K>,
+    ghost_links: Vec<Link>,
+    a1_in: List,
+    a1_out: List,
+    am: List,
+    capacity: usize,
+    removals: RemovalQueue<K, V>,
+}
+
+/// Which queue a key is on, and its index in the matching slab.
+#[derive(Clone, Copy)]
+enum Slot {
+    In(usize),
+    Out(usize),
+    Main(usize),
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TwoQueueCache<K, V> {
+    /// Create a new cache holding at most `capacity` entries. It also
+    /// remembers up to half as many keys of entries evicted from A1in.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            entries: Slab::new(),
+            entry_links: Vec::new(),
+            ghosts: Slab::new(),
+            ghost_links: Vec::new(),
+            a1_in: List::new(),
+            a1_out: List::new(),
+            am: List::new(),
+            capacity,
+            removals: RemovalQueue::new(),
+        }
+    }
+
+    /// Call `listener` with the key, value and cause of every entry that
+    /// leaves the cache.
+    pub fn with_removal_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.removals.set_listener(Arc::new(listener));
+        self
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.a1_in.len() + self.am.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the key is cached, without marking it as used.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        matches!(self.map.get(key), Some(Slot::In(_) | Slot::Main(_)))
+    }
+
+    /// Get a reference to a value without marking it as used.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match *self.map.get(key)? {
+            Slot::In(idx) | Slot::Main(idx) => Some(&self.entries.get(idx).value),
+            Slot::Out(_) => None,
+        }
+    }
+
+    /// Get a value associated with a key. Hits in Am move the entry to its
+    /// front; hits in A1in leave it in place.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = match *self.map.get(key)? {
+            Slot::In(idx) => idx,
+            Slot::Main(idx) => {
+                self.am.move_to_front(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::Out(_) => return None,
+        };
+        Some(self.entries.get(idx).value.clone())
+    }
+
+    /// Insert a key-value pair into the cache.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let remembered = match self.map.get(&key).copied() {
+            Some(Slot::In(idx) | Slot::Main(idx)) => {
+                self.get(&key);
+                let old_value = std::mem::replace(&mut self.entries.get_mut(idx).value, value);
+                self.removals.record(key, old_value, RemovalCause::Replaced);
+                self.removals.notify();
+                return;
+            }
+            Some(Slot::Out(ghost)) => {
+                self.a1_out.unlink(&mut self.ghost_links, ghost);
+                self.ghosts.remove(ghost);
+                self.map.remove(&key);
+                true
+            }
+            None => false,
+        };
+        if self.len() >= self.capacity {
+            self.reclaim();
+        }
+
+        let idx = self.entries.insert(Node {
+            key: key.clone(),
+            value,
+        });
+        let slot = if remembered {
+            self.am.push_front(&mut self.entry_links, idx);
+            Slot::Main(idx)
+        } else {
+            self.a1_in.push_front(&mut self.entry_links, idx);
+            Slot::In(idx)
+        };
+        self.map.insert(key, slot);
+        self.removals.notify();
+    }
+
+    /// Remove a key-value pair from the cache.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = match *self.map.get(key)? {
+            Slot::In(idx) => {
+                self.a1_in.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::Main(idx) => {
+                self.am.unlink(&mut self.entry_links, idx);
+                idx
+            }
+            Slot::Out(_) => return None,
+        };
+        let node = self.entries.remove(idx);
+        self.map.remove(key);
+        self.removals.record_cloned(node.key, &node.value, RemovalCause::Explicit);
+        self.removals.notify();
+        Some(node.value)
+    }
+
+    /// Remove every entry, reporting each as `RemovalCause::Cleared`, and
+    /// forget the remembered keys.
+    pub fn clear(&mut self) {
+        for list in [&mut self.a1_in, &mut self.am] {
+            while let Some(idx) = list.back() {
+                list.unlink(&mut self.entry_links, idx);
+                let node = self.entries.remove(idx);
+                self.removals.record(node.key, node.value, RemovalCause::Cleared);
+            }
+        }
+        while let Some(idx) = self.a1_out.back() {
+            self.a1_out.unlink(&mut self.ghost_links, idx);
+            self.ghosts.remove(idx);
+        }
+        self.map.clear();
+        self.removals.notify();
+    }
+
+    /// Change the capacity, keeping the cached entries where possible.
+    ///
+    /// When shrinking, entries are evicted as they would be to make room for
+    /// new keys. They are reported to the removal listener as
+    /// `RemovalCause::Evicted` and also returned, in eviction order.
+    pub fn resize(&mut self, new_capacity: usize) -> Vec<(K, V)> {
+        self.capacity = new_capacity;
+        self.removals.collect_evictions();
+        while self.len() > self.capacity {
+            self.reclaim();
+        }
+        while self.a1_out.len() > self.out_capacity() {
+            self.forget_oldest();
+        }
+        let evicted = self.removals.take_evictions();
+        self.removals.notify();
+        evicted
+    }
+
+    /// Entries A1in may hold before it gives up space to Am.
+    fn in_capacity(&self) -> usize {
+        (self.capacity / 4).max(1)
+    }
+
+    /// Keys A1out remembers.
+    fn out_capacity(&self) -> usize {
+        self.capacity / 2
+    }
+
+    /// Evict one entry: the oldest of A1in if it is over its share, leaving
+    /// its key in A1out, and otherwise the least recently used of Am.
+    fn reclaim(&mut self) {
+        let from_in = self.a1_in.len() > self.in_capacity() || self.am.is_empty();
+        if from_in {
+            let Some(idx) = self.a1_in.back() else {
+                return;
+            };
+            self.a1_in.unlink(&mut self.entry_links, idx);
+            let node = self.entries.remove(idx);
+            self.remember(node.key.clone());
+            self.removals.record(node.key, node.value, RemovalCause::Evicted);
+        } else if let Some(idx) = self.am.back() {
+            self.am.unlink(&mut self.entry_links, idx);
+            let node = self.entries.remove(idx);
+            self.map.remove(&node.key);
+            self.removals.record(node.key, node.value, RemovalCause::Evicted);
+        }
+    }
+
+    /// Put a key evicted from A1in at the front of A1out.
+    fn remember(&mut self, key: K) {
+        if self.out_capacity() == 0 {
+            self.map.remove(&key);
+            return;
+        }
+        if self.a1_out.len() >= self.out_capacity() {
+            self.forget_oldest();
+        }
+        let ghost = self.ghosts.insert(key.clone());
+        self.a1_out.push_front(&mut self.ghost_links, ghost);
+        *self.map.get_mut(&key).expect("evicted key is still mapped") = Slot::Out(ghost);
+    }
+
+    fn forget_oldest(&mut self) {
+        if let Some(idx) = self.a1_out.back() {
+            self.a1_out.unlink(&mut self.ghost_links, idx);
+            let key = self.ghosts.remove(idx);
+            self.map.remove(&key);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> BoundedCache<K, V> for TwoQueueCache<K, V> {
+    fn insert(&mut self, key: K, value: V) {
+        TwoQueueCache::insert(self, key, value)
+    }
+
+    fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        TwoQueueCache::get(self, key)
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        TwoQueueCache::remove(self, key)
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        TwoQueueCache::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        TwoQueueCache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        TwoQueueCache::capacity(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn in_main(cache: &TwoQueueCache<u32, u32>, key: u32) -> bool {
+        matches!(cache.map.get(&key), Some(Slot::Main(_)))
+    }
+
+    #[test]
+    fn two_queue_cache_works() {
+        let mut cache = TwoQueueCache::new(4);
+        for key in 0..4 {
+            cache.insert(key, key * 10);
+        }
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.get(&3), Some(30));
+        assert_eq!(cache.remove(&3), Some(30));
+        assert!(!cache.contains_key(&3));
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_stores_nothing() {
+        let mut cache = TwoQueueCache::new(0);
+        cache.insert(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn keys_seen_again_move_to_am() {
+        let mut cache = TwoQueueCache::new(4);
+        for key in 1..=5 {
+            cache.insert(key, key);
+        }
+        // 1 was pushed out of A1in and is only remembered
+        assert!(!cache.contains_key(&1));
+        cache.insert(1, 1);
+        assert!(in_main(&cache, 1));
+    }
+
+    #[test]
+    fn scans_do_not_flush_am() {
+        let mut cache = TwoQueueCache::new(8);
+        for hot in 0..4 {
+            cache.insert(hot, hot);
+        }
+        for filler in 100..108 {
+            cache.insert(filler, filler);
+        }
+        // The hot keys come back while still remembered
+        for hot in 0..4 {
+            cache.insert(hot, hot);
+            assert!(in_main(&cache, hot));
+        }
+
+        for scan in 1_000..2_000 {
+            cache.insert(scan, scan);
+            cache.get(&scan);
+        }
+        assert!((0..4).all(|hot| cache.contains_key(&hot)));
+    }
+
+    #[test]
+    fn hits_in_a1_in_do_not_reorder() {
+        let mut cache = TwoQueueCache::new(4);
+        for key in 1..=4 {
+            cache.insert(key, key);
+        }
+        cache.get(&1);
+        cache.insert(5, 5);
+        assert!(!cache.contains_key(&1));
+    }
+
+    #[test]
+    fn resize_returns_evicted_entries() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = TwoQueueCache::new(4)
+            .with_removal_listener(move |key, _, cause| sink.lock().unwrap().push((key, cause)));
+        for key in 1..=4 {
+            cache.insert(key, key);
+        }
+        cache.insert(4, 40);
+        assert_eq!(cache.resize(2), vec![(1, 1), (2, 2)]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (4, RemovalCause::Replaced),
+                (1, RemovalCause::Evicted),
+                (2, RemovalCause::Evicted),
+            ]
+        );
+    }
+}
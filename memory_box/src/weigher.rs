@@ -0,0 +1,28 @@
+/// Computes how much of a cache's capacity an entry uses.
+///
+/// Caches built with a weigher bound the sum of entry weights instead of the
+/// number of entries, so a few large values cannot push memory use past the
+/// budget. Weights are computed once, when an entry is inserted.
+pub trait Weigher<K, V> {
+    /// Weight of the entry, in whatever unit the cache capacity is given in.
+    fn weigh(&self, key: &K, value: &V) -> usize;
+}
+
+/// Weigher that counts every entry as 1, making capacity an entry count.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UnitWeigher;
+
+impl<K, V> Weigher<K, V> for UnitWeigher {
+    fn weigh(&self, _key: &K, _value: &V) -> usize {
+        1
+    }
+}
+
+impl<K, V, F> Weigher<K, V> for F
+where
+    F: Fn(&K, &V) -> usize,
+{
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        self(key, value)
+    }
+}